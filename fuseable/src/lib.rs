@@ -1,20 +1,23 @@
-#![feature(core_intrinsics)]
 use failure::{Error, Fail};
 use fuse_mt::*;
 use itertools::Itertools;
-use ::log::{error, log};
+use ::log::error;
 use lru::LruCache;
 use std::{
     collections::{BTreeMap, HashMap},
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    fmt,
     ops::{Deref, DerefMut},
     os::raw::c_int,
     path::Path,
     result,
     str::FromStr,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
-use time::*;
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -37,6 +40,10 @@ pub enum FuseableError {
     IndexOutOfBounds { idx: usize, len: usize },
     #[fail(display = "the action {} is not supported for type {}", action, typename)]
     Unsupported { action: String, typename: String },
+    #[fail(display = "invalid value: {}", reason)]
+    InvalidValue { reason: String },
+    #[fail(display = "the deadline of the request passed")]
+    DeadlineExceeded,
 }
 
 impl FuseableError {
@@ -61,14 +68,251 @@ impl FuseableError {
             typename: typename.as_ref().to_string(),
         })
     }
+
+    pub fn invalid_value<T: AsRef<str>>(reason: T) -> Error {
+        Error::from(FuseableError::InvalidValue { reason: reason.as_ref().to_string() })
+    }
+
+    pub fn deadline_exceeded() -> Error { Error::from(FuseableError::DeadlineExceeded) }
+}
+
+// implemented by errors that know which errno they should be reported as by
+// FuseableWrapper
+pub trait Errno: Fail + Sized {
+    fn errno(&self) -> c_int;
+
+    // failure::Error can only be downcast to concrete types, so the errno has to
+    // be kept next to the error when converting it
+    fn into_error(self) -> Error {
+        Error::from(ErrnoError { errno: self.errno(), error: Box::new(self) })
+    }
+}
+
+impl Errno for FuseableError {
+    fn errno(&self) -> c_int {
+        match self {
+            FuseableError::NotADirectory { .. } => libc::ENOTDIR,
+            FuseableError::NotFound { .. } | FuseableError::IndexOutOfBounds { .. } => libc::ENOENT,
+            FuseableError::Unsupported { action, .. } if action == "write" => libc::EROFS,
+            FuseableError::Unsupported { .. } => libc::EACCES,
+            FuseableError::InvalidValue { .. } => libc::EINVAL,
+            FuseableError::DeadlineExceeded => libc::ETIMEDOUT,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ErrnoError {
+    errno: c_int,
+    error: Box<dyn Fail>,
+}
+
+impl fmt::Display for ErrnoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.error) }
+}
+
+impl Fail for ErrnoError {
+    fn cause(&self) -> Option<&dyn Fail> { self.error.cause() }
+}
+
+// the errno an error is reported as, errors that don't specify one are assumed
+// to be caused by the hardware
+pub fn errno(error: &Error) -> c_int {
+    for cause in error.iter_chain() {
+        if let Some(e) = cause.downcast_ref::<ErrnoError>() {
+            return e.errno;
+        }
+
+        if let Some(e) = cause.downcast_ref::<FuseableError>() {
+            return e.errno();
+        }
+
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return e.raw_os_error().unwrap_or(libc::EIO);
+        }
+
+        if cause.downcast_ref::<std::num::ParseIntError>().is_some()
+            || cause.downcast_ref::<std::num::ParseFloatError>().is_some()
+            || cause.downcast_ref::<std::str::ParseBoolError>().is_some()
+            || cause.downcast_ref::<std::string::FromUtf8Error>().is_some()
+            || cause.downcast_ref::<std::str::Utf8Error>().is_some()
+        {
+            return libc::EINVAL;
+        }
+    }
+
+    libc::EIO
+}
+
+// logs the error and returns its errno, for use in FuseableWrapper
+fn report(error: Error) -> c_int {
+    error!("{}", error);
+
+    errno(&error)
 }
 
-pub fn type_name<T>(_: &T) -> &'static str { unsafe { std::intrinsics::type_name::<T>() } }
+pub fn type_name<T>(_: &T) -> &'static str { std::any::type_name::<T>() }
+
+// who may access a node, FuseableWrapper reports this as the mode and owner of
+// the file and checks it when the file is opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Permissions {
+    pub readable: bool,
+    pub writable: bool,
+    // None is root, nodes that have neither an owner nor a group can be written
+    // by everyone, otherwise only by the owner and the members of the group
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Default for Permissions {
+    fn default() -> Permissions { Permissions::access(true, true) }
+}
+
+impl Permissions {
+    pub fn access(readable: bool, writable: bool) -> Permissions {
+        Permissions { readable, writable, uid: None, gid: None }
+    }
+
+    pub fn owned(uid: Option<u32>, gid: Option<u32>) -> Permissions {
+        Permissions { uid, gid, ..Permissions::default() }
+    }
+
+    // the permissions of a node below a node with the permissions parent, the
+    // owner and group are inherited unless the node has its own
+    pub fn below(self, parent: Permissions) -> Permissions {
+        Permissions {
+            readable: self.readable && parent.readable,
+            writable: self.writable && parent.writable,
+            uid: self.uid.or(parent.uid),
+            gid: self.gid.or(parent.gid),
+        }
+    }
+
+    fn restricted(&self) -> bool { self.uid.is_some() || self.gid.is_some() }
+
+    fn mode(&self, is_dir: bool) -> u16 {
+        let read = if self.readable { 0o4 } else { 0 };
+        let write = if self.writable { 0o2 } else { 0 };
+        let others = if self.restricted() { read } else { read | write };
+
+        if is_dir {
+            // entries can not be created, so this only mirrors who may write below
+            if self.restricted() {
+                0o755
+            } else {
+                0o777
+            }
+        } else {
+            (read | write) << 6 | (read | write) << 3 | others
+        }
+    }
+
+    // uid 0 may do everything the node supports
+    fn allows(&self, uid: u32, groups: &[u32], read: bool, write: bool) -> bool {
+        let may_write = !self.restricted()
+            || uid == 0
+            || self.uid == Some(uid)
+            || self.gid.map(|gid| groups.contains(&gid)).unwrap_or(false);
+
+        (!read || self.readable) && (!write || (self.writable && may_write))
+    }
+}
+
+// the context of the filesystem request that caused an access, everything is
+// None for accesses that don't originate from FuseableWrapper
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub fh: Option<u64>,
+    // the unique id fuse assigned to the request
+    pub unique: Option<u64>,
+    // only set if the FuseableWrapper was given a timeout
+    pub deadline: Option<Instant>,
+}
+
+impl Request {
+    fn new(req: &RequestInfo, fh: Option<u64>, timeout: Option<Duration>) -> Request {
+        Request {
+            uid: Some(req.uid),
+            gid: Some(req.gid),
+            fh,
+            unique: Some(req.unique),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    // for nodes that are slow to access, to give up instead of blocking the caller
+    // longer than they want to wait
+    pub fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => Err(FuseableError::deadline_exceeded()),
+            _ => Ok(()),
+        }
+    }
+}
 
 pub trait Fuseable {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool>;
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>>;
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()>;
+
+    // the same as is_dir, read and write, but with the context of the request that
+    // caused the access. FuseableWrapper only calls these, nodes that don't care
+    // about the request implement the methods above and nodes that contain other
+    // nodes pass the request on to them
+    fn is_dir_for(&self, _request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        self.is_dir(path)
+    }
+
+    fn read_for(
+        &self,
+        _request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        self.read(path)
+    }
+
+    fn write_for(
+        &mut self,
+        _request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.write(path, value)
+    }
+
+    // metadata of the node at path as (name, value) pairs, FuseableWrapper
+    // exposes these as user.ctrl.<name> extended attributes
+    fn xattrs(&self, _path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        Ok(Vec::new())
+    }
+
+    // the last time the content of the node at path changed, if that is known,
+    // FuseableWrapper reports it as the mtime of the file
+    fn modified(&self, _path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    // who may access the node at path, relative to its parent
+    fn permissions(&self, _path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        Ok(Permissions::default())
+    }
+}
+
+// the entries listed by node, or none if it is not a directory
+pub fn entries<T: Fuseable + ?Sized>(node: &T) -> Result<Vec<String>> {
+    match node.read(&mut std::iter::empty())? {
+        Either::Left(entries) => Ok(entries),
+        Either::Right(_) => Ok(Vec::new()),
+    }
+}
+
+// used by the derive to find the entries of flattened fields, so only entries
+// that are listed can be found
+pub fn has_entry<T: Fuseable + ?Sized>(node: &T, name: &str) -> bool {
+    entries(node).map(|entries| entries.iter().any(|entry| entry == name)).unwrap_or(false)
 }
 
 macro_rules! impl_fuseable_with_to_string {
@@ -123,6 +367,10 @@ impl_fuseable_with_to_string!(f64);
 
 impl<T: Fuseable> Fuseable for Vec<T> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
         match path.next() {
             Some(idx) => {
                 let idx = idx.parse::<usize>()?;
@@ -131,13 +379,21 @@ impl<T: Fuseable> Fuseable for Vec<T> {
                     .get(idx)
                     .ok_or_else(|| FuseableError::index_out_of_bounds(idx, self.len()))?;
 
-                Fuseable::is_dir(v, path)
+                Fuseable::is_dir_for(v, request, path)
             }
             None => Ok(true),
         }
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(idx) => {
                 let idx = idx.parse::<usize>()?;
@@ -146,13 +402,22 @@ impl<T: Fuseable> Fuseable for Vec<T> {
                     .get(idx)
                     .ok_or_else(|| FuseableError::index_out_of_bounds(idx, self.len()))?;
 
-                Fuseable::read(v, path)
+                Fuseable::read_for(v, request, path)
             }
             None => Ok(Either::Left((0..self.len()).map(|v| v.to_string()).collect())),
         }
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
         match path.next() {
             Some(idx) => {
                 let idx = idx.parse::<usize>()?;
@@ -162,52 +427,196 @@ impl<T: Fuseable> Fuseable for Vec<T> {
                     .get_mut(idx)
                     .ok_or_else(|| FuseableError::index_out_of_bounds(idx, len))?;
 
-                Fuseable::write(v, path, value)
+                Fuseable::write_for(v, request, path, value)
             }
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        match path.next() {
+            Some(idx) => {
+                let idx = idx.parse::<usize>()?;
+
+                let v = self
+                    .get(idx)
+                    .ok_or_else(|| FuseableError::index_out_of_bounds(idx, self.len()))?;
+
+                Fuseable::xattrs(v, path)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        match path.next() {
+            Some(idx) => {
+                let idx = idx.parse::<usize>()?;
+
+                let v = self
+                    .get(idx)
+                    .ok_or_else(|| FuseableError::index_out_of_bounds(idx, self.len()))?;
+
+                Fuseable::permissions(v, path)
+            }
+            None => Ok(Permissions::default()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        match path.next() {
+            Some(idx) => {
+                let idx = idx.parse::<usize>()?;
+
+                let v = self
+                    .get(idx)
+                    .ok_or_else(|| FuseableError::index_out_of_bounds(idx, self.len()))?;
+
+                Fuseable::modified(v, path)
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 impl<T: Fuseable> Fuseable for Arc<Mutex<T>> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
-        self.lock().unwrap().is_dir(path)
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        self.lock().unwrap().is_dir_for(request, path)
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
-        self.lock().unwrap().read(path)
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        self.lock().unwrap().read_for(request, path)
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
-        self.lock().unwrap().write(path, value)
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.lock().unwrap().write_for(request, path, value)
+    }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        self.lock().unwrap().xattrs(path)
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        self.lock().unwrap().permissions(path)
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        self.lock().unwrap().modified(path)
     }
 }
 
 impl<T: Fuseable + ?Sized> Fuseable for Box<T> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
-        Deref::deref(self).is_dir(path)
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Deref::deref(self).is_dir_for(request, path)
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
-        Deref::deref(self).read(path)
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        Deref::deref(self).read_for(request, path)
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
-        DerefMut::deref_mut(self).write(path, value)
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        DerefMut::deref_mut(self).write_for(request, path, value)
+    }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        Deref::deref(self).xattrs(path)
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        Deref::deref(self).permissions(path)
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        Deref::deref(self).modified(path)
     }
 }
 
 impl<T: Fuseable> Fuseable for Mutex<T> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
-        self.lock().unwrap().is_dir(path)
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        self.lock().unwrap().is_dir_for(request, path)
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
-        self.lock().unwrap().read(path)
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        self.lock().unwrap().read_for(request, path)
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
-        self.lock().unwrap().write(path, value)
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.lock().unwrap().write_for(request, path, value)
+    }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        self.lock().unwrap().xattrs(path)
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        self.lock().unwrap().permissions(path)
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        self.lock().unwrap().modified(path)
     }
 }
 
@@ -233,32 +642,78 @@ impl<'a> Fuseable for &'a str {
 
 impl<TY: Fuseable> Fuseable for Option<TY> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
         match self {
-            Some(v) => Fuseable::is_dir(v, path),
+            Some(v) => Fuseable::is_dir_for(v, request, path),
             None => Ok(false),
         }
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
         match self {
-            Some(v) => Fuseable::read(v, path),
+            Some(v) => Fuseable::read_for(v, request, path),
             None => Ok(Either::Right("None".to_string())),
         }
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
         match self {
-            Some(v) => Fuseable::write(v, path, value),
+            Some(v) => Fuseable::write_for(v, request, path, value),
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        match self {
+            Some(v) => Fuseable::xattrs(v, path),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        match self {
+            Some(v) => Fuseable::permissions(v, path),
+            None => Ok(Permissions::default()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        match self {
+            Some(v) => Fuseable::modified(v, path),
+            None => Ok(None),
+        }
+    }
 }
 
 impl<'a, VT: Fuseable> Fuseable for BTreeMap<String, VT> {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
         match path.next() {
             Some(name) => match self.get(&name.to_string()) {
-                Some(inner) => inner.is_dir(path),
+                Some(inner) => inner.is_dir_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => Ok(true),
@@ -266,9 +721,17 @@ impl<'a, VT: Fuseable> Fuseable for BTreeMap<String, VT> {
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(name) => match self.get(&name.to_string()) {
-                Some(inner) => inner.read(path),
+                Some(inner) => inner.read_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => {
@@ -279,14 +742,53 @@ impl<'a, VT: Fuseable> Fuseable for BTreeMap<String, VT> {
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
         match path.next() {
             Some(name) => match self.get_mut(&name.to_string()) {
-                Some(inner) => inner.write(path, value),
+                Some(inner) => inner.write_for(request, path, value),
                 None => Err(FuseableError::not_found(name)),
             },
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        match path.next() {
+            Some(name) => match self.get(&name.to_string()) {
+                Some(inner) => inner.xattrs(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        match path.next() {
+            Some(name) => match self.get(&name.to_string()) {
+                Some(inner) => inner.permissions(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Permissions::default()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        match path.next() {
+            Some(name) => match self.get(&name.to_string()) {
+                Some(inner) => inner.modified(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(None),
+        }
+    }
 }
 
 impl<
@@ -299,9 +801,13 @@ where
     <KT as std::str::FromStr>::Err: std::error::Error + Send + Sync + 'static,
 {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
         match path.next() {
             Some(name) => match self.get(&name.parse()?) {
-                Some(inner) => inner.is_dir(path),
+                Some(inner) => inner.is_dir_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => Ok(true),
@@ -309,9 +815,17 @@ where
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(name) => match self.get(&name.parse()?) {
-                Some(inner) => inner.read(path),
+                Some(inner) => inner.read_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => {
@@ -322,14 +836,53 @@ where
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
         match path.next() {
             Some(name) => match self.get_mut(&name.parse()?) {
-                Some(inner) => inner.write(path, value),
+                Some(inner) => inner.write_for(request, path, value),
                 None => Err(FuseableError::not_found(name)),
             },
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.xattrs(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.permissions(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Permissions::default()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.modified(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(None),
+        }
+    }
 }
 
 #[cfg(feature = "bimap")]
@@ -342,9 +895,13 @@ where
     <KT as std::str::FromStr>::Err: std::error::Error + Send + Sync + 'static,
 {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        Fuseable::is_dir_for(self, &Request::default(), path)
+    }
+
+    fn is_dir_for(&self, request: &Request, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
         match path.next() {
             Some(name) => match self.get_left(&name.parse()?) {
-                Some(inner) => inner.is_dir(path),
+                Some(inner) => inner.is_dir_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => Ok(true),
@@ -352,9 +909,17 @@ where
     }
 
     fn read(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+        Fuseable::read_for(self, &Request::default(), path)
+    }
+
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(name) => match self.get_left(&name.parse()?) {
-                Some(inner) => inner.read(path),
+                Some(inner) => inner.read_for(request, path),
                 None => Err(FuseableError::not_found(name)),
             },
             None => {
@@ -367,12 +932,21 @@ where
     }
 
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+        Fuseable::write_for(self, &Request::default(), path, value)
+    }
+
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
         match path.next() {
             Some(name) => {
                 let parsed_name = name.parse()?;
                 let mut right =
                     self.remove_left(&parsed_name).ok_or_else(|| FuseableError::not_found(name))?;
-                let ret = Fuseable::write(&mut right, path, value);
+                let ret = Fuseable::write_for(&mut right, request, path, value);
                 self.insert(parsed_name, right);
 
                 ret
@@ -380,6 +954,36 @@ where
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        match path.next() {
+            Some(name) => match self.get_left(&name.parse()?) {
+                Some(inner) => inner.xattrs(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        match path.next() {
+            Some(name) => match self.get_left(&name.parse()?) {
+                Some(inner) => inner.permissions(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Permissions::default()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        match path.next() {
+            Some(name) => match self.get_left(&name.parse()?) {
+                Some(inner) => inner.modified(path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(None),
+        }
+    }
 }
 
 /*
@@ -505,24 +1109,395 @@ impl Fuseable for CachedFuseable {
     fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
         Fuseable::write(self.fuseable.deref_mut(), path, value)
     }
+
+    // only writes go past the cache, so they are the only accesses the request is
+    // passed on for
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        Fuseable::write_for(self.fuseable.deref_mut(), request, path, value)
+    }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+        Fuseable::xattrs(self.fuseable.deref(), path)
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Permissions> {
+        Fuseable::permissions(self.fuseable.deref(), path)
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> Result<Option<SystemTime>> {
+        Fuseable::modified(self.fuseable.deref(), path)
+    }
+}
+
+// like Fuseable, for nodes that need some context to be accessed, for example
+// the value of a register needs the register set it belongs to. the derive
+// implements this for virtual fields that have a context, everything else is
+// forwarded to the Fuseable implementation. writing takes &self, as the context
+// usually contains the node itself, so the other fields have to be written using
+// Fuseable::write_for instead. the request is passed on to the virtual fields,
+// so slow ones (like scripts) can check its deadline between their steps
+pub trait FuseableContext<C: ?Sized> {
+    fn is_dir_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<bool>;
+    fn read_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>>;
+    fn write_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()>;
+}
+
+impl<C: ?Sized, T: FuseableContext<C> + ?Sized> FuseableContext<C> for Box<T> {
+    fn is_dir_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<bool> {
+        Deref::deref(self).is_dir_with(context, request, path)
+    }
+
+    fn read_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        Deref::deref(self).read_with(context, request, path)
+    }
+
+    fn write_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        Deref::deref(self).write_with(context, request, path, value)
+    }
+}
+
+impl<C: ?Sized, VT: FuseableContext<C>> FuseableContext<C> for BTreeMap<String, VT> {
+    fn is_dir_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<bool> {
+        match path.next() {
+            Some(name) => match self.get(name) {
+                Some(inner) => inner.is_dir_with(context, request, path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(true),
+        }
+    }
+
+    fn read_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        match path.next() {
+            Some(name) => match self.get(name) {
+                Some(inner) => inner.read_with(context, request, path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Either::Left(self.keys().cloned().collect())),
+        }
+    }
+
+    fn write_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        match path.next() {
+            Some(name) => match self.get(name) {
+                Some(inner) => inner.write_with(context, request, path, value),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Err(FuseableError::unsupported("write", type_name(&self))),
+        }
+    }
 }
 
+impl<
+        C: ?Sized,
+        VT: FuseableContext<C>,
+        KT: FromStr + ToString + Sync + Send + Eq + Hash + Clone,
+        S: std::hash::BuildHasher + Send + Sync,
+    > FuseableContext<C> for HashMap<KT, VT, S>
+where
+    <KT as std::str::FromStr>::Err: std::error::Error + Send + Sync + 'static,
+{
+    fn is_dir_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<bool> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.is_dir_with(context, request, path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(true),
+        }
+    }
+
+    fn read_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.read_with(context, request, path),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => {
+                let keys: Vec<_> = self.keys().cloned().map(|k| k.to_string()).collect();
+                Ok(Either::Left(keys))
+            }
+        }
+    }
+
+    fn write_with(
+        &self,
+        context: &C,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        match path.next() {
+            Some(name) => match self.get(&name.parse()?) {
+                Some(inner) => inner.write_with(context, request, path, value),
+                None => Err(FuseableError::not_found(name)),
+            },
+            None => Err(FuseableError::unsupported("write", type_name(&self))),
+        }
+    }
+}
+
+// the state of an open file, reads are served from the content at the time the
+// file was opened and writes are collected until the file is flushed, so
+// partial writes and reads in multiple chunks see a consistent value
+struct OpenFile {
+    path: String,
+    content: Vec<u8>,
+    written: Option<Vec<u8>>,
+    // whether the access check in open allowed writing, so it is not repeated
+    // (and the groups are not looked up again) for every written chunk
+    writable: bool,
+}
+
+impl OpenFile {
+    fn size(&self) -> u64 { self.written.as_ref().unwrap_or(&self.content).len() as u64 }
+}
+
+// the file contents change without the kernel knowing about it, so its page
+// cache is bypassed
+const FOPEN_DIRECT_IO: u32 = 1;
+
+// files hold a single value, so anything larger is a mistake and would only
+// allocate the memory for it
+const MAX_FILE_SIZE: u64 = 1 << 20;
+
 pub struct FuseableWrapper<'a> {
-    inner: RwLock<Box<dyn Fuseable + 'a>>,
+    inner: RwLock<Box<dyn Fuseable + Send + Sync + 'a>>,
+    open_files: Mutex<HashMap<u64, OpenFile>>,
+    next_fh: AtomicU64,
+    timeout: Option<Duration>,
     /*    getattr_cache: RwLock<LruCache<String, Result<bool, ()>>>,
      *    readdir_cache: RwLock<LruCache<String, Result<Either<Vec<String>, String>, ()>>>, */
 }
 
 impl<'a> FuseableWrapper<'a> {
-    pub fn new<T: Fuseable + 'a>(f: T) -> FuseableWrapper<'a> {
+    pub fn new<T: Fuseable + Send + Sync + 'a>(f: T) -> FuseableWrapper<'a> {
         FuseableWrapper {
             inner: RwLock::new(Box::new(f)),
+            open_files: Mutex::new(HashMap::new()),
+            // 0 is used for directories
+            next_fh: AtomicU64::new(1),
+            timeout: None,
             /*
             getattr_cache: RwLock::new(LruCache::new(65535)),
             readdir_cache: RwLock::new(LruCache::new(65535)),
             */
         }
     }
+
+    // how long a single filesystem request may take, this is passed on to the nodes
+    // as the deadline of the request
+    pub fn with_timeout(self, timeout: Duration) -> FuseableWrapper<'a> {
+        FuseableWrapper { timeout: Some(timeout), ..self }
+    }
+
+    fn request(&self, req: &RequestInfo, fh: Option<u64>) -> Request {
+        Request::new(req, fh, self.timeout)
+    }
+
+    fn is_dir(&self, request: &Request, path: &str) -> Result<bool> {
+        Fuseable::is_dir_for((&*self.inner.read().unwrap()).deref(), request, &mut components(path))
+    }
+
+    fn xattrs(&self, path: &str) -> result::Result<Vec<(String, String)>, c_int> {
+        Fuseable::xattrs((&*self.inner.read().unwrap()).deref(), &mut components(path))
+            .map_err(report)
+    }
+
+    // errors are only logged, a file whose modification time is unknown is
+    // reported as never modified
+    fn modified(&self, path: &str) -> SystemTime {
+        Fuseable::modified((&*self.inner.read().unwrap()).deref(), &mut components(path))
+            .map_err(report)
+            .ok()
+            .and_then(|modified| modified)
+            .unwrap_or(std::time::UNIX_EPOCH)
+    }
+
+    fn permissions(&self, path: &str) -> result::Result<Permissions, c_int> {
+        Fuseable::permissions((&*self.inner.read().unwrap()).deref(), &mut components(path))
+            .map_err(report)
+    }
+
+    // the request only carries the primary group, so the other groups of the user
+    // are looked up if they matter
+    fn check_access(&self, req: &RequestInfo, path: &str, read: bool, write: bool) -> ResultEmpty {
+        let permissions = self.permissions(path)?;
+
+        let groups = match permissions.gid {
+            Some(gid) if gid != req.gid => groups(req.uid, req.gid),
+            _ => vec![req.gid],
+        };
+
+        if permissions.allows(req.uid, &groups, read, write) {
+            Ok(())
+        } else {
+            Err(libc::EACCES)
+        }
+    }
+
+    fn read_file(&self, request: &Request, path: &str) -> result::Result<Vec<u8>, c_int> {
+        let inner = self.inner.read().unwrap();
+
+        match Fuseable::read_for((&*inner).deref(), request, &mut components(path)) {
+            Ok(Either::Left(_)) => Err(libc::EISDIR),
+            Ok(Either::Right(s)) => Ok(s.into_bytes()),
+            Err(e) => Err(report(e)),
+        }
+    }
+
+    // writes what was written to an open file since it was last flushed
+    fn flush_file(&self, req: &RequestInfo, fh: u64) -> ResultEmpty {
+        let (path, written) = {
+            let mut open_files = self.open_files.lock().unwrap();
+            let file = open_files.get_mut(&fh).ok_or(libc::EBADF)?;
+
+            match file.written.take() {
+                Some(written) => {
+                    // later reads through this handle see what was written
+                    file.content = written.clone();
+                    (file.path.clone(), written)
+                }
+                None => return Ok(()),
+            }
+        };
+
+        let result = Fuseable::write_for(
+            (&mut *self.inner.write().unwrap()).deref_mut(),
+            &self.request(req, Some(fh)),
+            &mut components(&path),
+            written,
+        )
+        .map_err(report);
+
+        result
+    }
+}
+
+// the extended attributes provided by Fuseable::xattrs are in the user
+// namespace, so they can be read without special privileges
+const XATTR_PREFIX: &str = "user.ctrl.";
+
+// a size of 0 asks for the size of the data, if the data is larger than size
+// the buffer of the caller is too small
+fn xattr_reply(data: Vec<u8>, size: u32) -> ResultXattr {
+    if size == 0 {
+        Ok(Xattr::Size(data.len() as u32))
+    } else if data.len() > size as usize {
+        Err(libc::ERANGE)
+    } else {
+        Ok(Xattr::Data(data))
+    }
+}
+
+// the groups uid is a member of, including its primary group gid
+fn groups(uid: u32, gid: u32) -> Vec<u32> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+
+    let ret =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+    if ret != 0 || result.is_null() {
+        return vec![gid];
+    }
+
+    let mut groups = vec![0; 64];
+    let mut count = groups.len() as c_int;
+
+    // count is set to the number of groups if the buffer is too small
+    while unsafe { libc::getgrouplist(passwd.pw_name, gid, groups.as_mut_ptr(), &mut count) } < 0 {
+        let len = (count as usize).max(groups.len() * 2);
+        groups.resize(len, 0);
+        count = len as c_int;
+    }
+
+    groups.truncate(count as usize);
+    groups
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> { path.split_terminator('/').skip(1) }
+
+fn file_attr(is_dir: bool, size: u64, mtime: SystemTime, permissions: Permissions) -> FileAttr {
+    FileAttr {
+        size,
+        blocks: (size + 511) / 512,
+        atime: std::time::UNIX_EPOCH,
+        mtime,
+        ctime: std::time::UNIX_EPOCH,
+        crtime: std::time::UNIX_EPOCH,
+        kind: if is_dir { FileType::Directory } else { FileType::RegularFile },
+        perm: permissions.mode(is_dir),
+        nlink: 2,
+        uid: permissions.uid.unwrap_or(0),
+        gid: permissions.gid.unwrap_or(0),
+        rdev: 0,
+        flags: 0,
+    }
 }
 
 impl<'a> FilesystemMT for FuseableWrapper<'a> {
@@ -530,171 +1505,212 @@ impl<'a> FilesystemMT for FuseableWrapper<'a> {
 
     // fn destroy(&self, _req: RequestInfo) {}
 
-    fn getattr(&self, _req: RequestInfo, path: &Path, fh: Option<u64>) -> ResultEntry {
+    fn getattr(&self, req: RequestInfo, path: &Path, fh: Option<u64>) -> ResultEntry {
         //        println!("getattr: {:?}", path);
-        if let Some(_fh) = fh {
-            //            println!("getattr: unhandled open file {}", fh);
-            Err(1)
-        } else {
-            //            fn read(&self, path: &mut dyn Iterator<Item = String>) ->
-            // Result<Either<Vec<String>, String>, ()>;
-
-            Fuseable::is_dir(
-                (&*self.inner.read().unwrap()).deref(),
-                &mut path.to_string_lossy().split_terminator('/').skip(1),
-            )
-            .map(|v| {
-                (
-                    std::time::Duration::from_secs(3600),
-                    FileAttr {
-                        size: 4096, /* TODO(robin): this is shitty, but needed to convince the
-                                     * vfs to actually use the results of a read */
-                        blocks: 0,
-                        atime: std::time::UNIX_EPOCH,
-                        mtime: std::time::UNIX_EPOCH,
-                        ctime: std::time::UNIX_EPOCH,
-                        crtime: std::time::UNIX_EPOCH,
-                        kind: if v { FileType::Directory } else { FileType::RegularFile },
-                        perm: 0o777,
-                        nlink: 2,
-                        uid: 0,
-                        gid: 0,
-                        rdev: 0,
-                        flags: 0,
-                    },
-                )
-            })
-            .map_err(|e| {
-                error!("{}", e);
-
-                0
-            })
-        }
-    }
-
-    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
-        //        println!("opendir: {:?} (flags = {:#o})", path, _flags);
+        let open_size =
+            fh.and_then(|fh| self.open_files.lock().unwrap().get(&fh).map(OpenFile::size));
 
-        match Fuseable::is_dir(
-            (&*self.inner.read().unwrap()).deref(),
-            &mut path.to_string_lossy().split_terminator('/').skip(1),
-        ) {
-            Ok(true) => Ok((0, 0)),
-            Ok(false) => Err(1),
-            Err(e) => {
-                error!("{}", e);
+        let path = path.to_string_lossy();
+
+        if let Some(size) = open_size {
+            let attr = file_attr(false, size, self.modified(&path), self.permissions(&path)?);
+
+            return Ok((std::time::Duration::from_secs(0), attr));
+        }
 
-                Err(1)
+        let request = self.request(&req, fh);
+
+        match self.is_dir(&request, &path) {
+            Ok(true) => {
+                let attr = file_attr(true, 0, std::time::UNIX_EPOCH, self.permissions(&path)?);
+
+                Ok((std::time::Duration::from_secs(3600), attr))
+            }
+            // reading the file to measure it would access the hardware and run the
+            // side effects of reading on every stat, the size of files without an
+            // open handle is reported as 0, which works as the page cache is bypassed
+            Ok(false) => {
+                let attr = file_attr(false, 0, self.modified(&path), self.permissions(&path)?);
+
+                Ok((std::time::Duration::from_secs(0), attr))
             }
+            Err(e) => Err(report(e)),
         }
     }
 
-    fn readdir(&self, _req: RequestInfo, path: &Path, _fh: u64) -> ResultReaddir {
+    fn opendir(&self, req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        //        println!("opendir: {:?} (flags = {:#o})", path, _flags);
+
+        match self.is_dir(&self.request(&req, None), &path.to_string_lossy()) {
+            Ok(true) => Ok((0, 0)),
+            Ok(false) => Err(libc::ENOTDIR),
+            Err(e) => Err(report(e)),
+        }
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &Path, fh: u64) -> ResultReaddir {
         //        println!("readdir: {:?}", path);
-        Fuseable::read(
+        Fuseable::read_for(
             (&*self.inner.read().unwrap()).deref(),
-            &mut path.to_string_lossy().split_terminator('/').skip(1),
+            &self.request(&req, Some(fh)),
+            &mut components(&path.to_string_lossy()),
         )
-        .map(|v| match v {
-            Either::Left(fields) => fields
+        .map_err(report)
+        .and_then(|v| match v {
+            Either::Left(fields) => Ok(fields
                 .iter()
                 .map(|f| DirectoryEntry { name: OsString::from(f), kind: FileType::Directory })
-                .collect(),
-            _ => unimplemented!(),
-        })
-        .map_err(|e| {
-            error!("{}", e);
-
-            1
+                .collect()),
+            Either::Right(_) => Err(libc::ENOTDIR),
         })
     }
-    fn open(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+
+    fn open(&self, req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
         //        println!("open: {:?} flags={:#x}", path, flags);
+        let path = path.to_string_lossy().into_owned();
+        let request = self.request(&req, None);
 
-        match Fuseable::is_dir(
-            (&*self.inner.read().unwrap()).deref(),
-            &mut path.to_string_lossy().split_terminator('/').skip(1),
-        ) {
-            Ok(false) => Ok((0, 0)),
-            Ok(true) => Err(1),
-            Err(e) => {
-                error!("{}", e);
-                Err(1)
-            }
+        match self.is_dir(&request, &path) {
+            Ok(false) => {}
+            Ok(true) => return Err(libc::EISDIR),
+            Err(e) => return Err(report(e)),
         }
+
+        let flags = flags as c_int;
+        let truncate = flags & libc::O_TRUNC != 0;
+
+        let (read, write) = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => (true, truncate),
+            libc::O_WRONLY => (false, true),
+            _ => (true, true),
+        };
+
+        // write only files can still be opened for reading and writing, so
+        // reading is only checked if the file is not written
+        self.check_access(&req, &path, read && !write, write)?;
+
+        let content = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => self.read_file(&request, &path)?,
+            // write only files can still be opened for reading and writing
+            libc::O_RDWR if !truncate => self.read_file(&request, &path).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        // a truncated file is only written if something is written to it or it is
+        // truncated again, so opening it with O_TRUNC alone has no effect
+        let fh = self.next_fh.fetch_add(1, Ordering::SeqCst);
+        self.open_files
+            .lock()
+            .unwrap()
+            .insert(fh, OpenFile { path, content, written: None, writable: write });
+
+        Ok((fh, FOPEN_DIRECT_IO))
     }
 
     fn read(
         &self,
         _req: RequestInfo,
-        path: &Path,
-        _fh: u64,
-        _offset: u64,
-        _size: u32,
-        result: impl FnOnce(result::Result<&[u8], c_int>),
-    ) {
+        _path: &Path,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult,
+    ) -> CallbackResult {
         //        println!("read: {:?} {:#x} @ {:#x}", path, size, offset);
+        let open_files = self.open_files.lock().unwrap();
 
-        match Fuseable::read(
-            (&*self.inner.read().unwrap()).deref(),
-            &mut path.to_string_lossy().split_terminator('/').skip(1),
-        ) {
-            Ok(Either::Left(_)) => result(Err(1)),
-            Ok(Either::Right(s)) => result(Ok(&s.into_bytes())),
-            Err(e) => {
-                error!("{}", e);
-                result(Err(1))
+        match open_files.get(&fh) {
+            Some(file) => {
+                let start = (offset as usize).min(file.content.len());
+                let end = (start + size as usize).min(file.content.len());
+
+                callback(Ok(&file.content[start..end]))
             }
+            None => callback(Err(libc::EBADF)),
         }
     }
 
     fn write(
         &self,
         _req: RequestInfo,
-        path: &Path,
-        _fh: u64,
-        _offset: u64,
+        _path: &Path,
+        fh: u64,
+        offset: u64,
         data: Vec<u8>,
         _flags: u32,
     ) -> ResultWrite {
         //        println!("write: {:?} {:#x} @ {:#x}", path, data.len(), offset);
-        // Ok(data.len() as u32)
-        let len = data.len();
-        Fuseable::write(
-            (&mut *self.inner.write().unwrap()).deref_mut(),
-            &mut path.to_string_lossy().split_terminator('/').skip(1),
-            data,
-        )
-        .map_err(|e| {
-            error!("{}", e);
-            1i32
-        })
-        .map(|_| len as u32)
+        let mut open_files = self.open_files.lock().unwrap();
+        let file = open_files.get_mut(&fh).ok_or(libc::EBADF)?;
+
+        if !file.writable {
+            return Err(libc::EACCES);
+        }
+
+        let end = offset.checked_add(data.len() as u64).ok_or(libc::EFBIG)?;
+
+        if end > MAX_FILE_SIZE {
+            return Err(libc::EFBIG);
+        }
+
+        let content = &file.content;
+        let written = file.written.get_or_insert_with(|| content.clone());
+        let end = end as usize;
+
+        if written.len() < end {
+            written.resize(end, 0);
+        }
+
+        written[offset as usize..end].copy_from_slice(&data);
+
+        Ok(data.len() as u32)
     }
 
-    fn truncate(
-        &self,
-        _req: RequestInfo,
-        _path: &Path,
-        _fh: Option<u64>,
-        _size: u64,
-    ) -> ResultEmpty {
+    fn truncate(&self, _req: RequestInfo, _path: &Path, fh: Option<u64>, size: u64) -> ResultEmpty {
         //        println!("truncate: {:?} to {:#x}", path, size);
-        Ok(())
-    }
+        // truncating a path that is not open has no effect, the value is only
+        // changed by writing to it
+        if let Some(fh) = fh {
+            if size > MAX_FILE_SIZE {
+                return Err(libc::EFBIG);
+            }
 
-    /*
+            let mut open_files = self.open_files.lock().unwrap();
+            let file = open_files.get_mut(&fh).ok_or(libc::EBADF)?;
+
+            if !file.writable {
+                return Err(libc::EACCES);
+            }
+
+            let content = &file.content;
+            file.written.get_or_insert_with(|| content.clone()).resize(size as usize, 0);
+        }
 
-    fn release(&self, _req: RequestInfo, path: &Path, fh: u64, _flags: u32, _lock_owner: u64, _flush: bool) -> ResultEmpty {
-        println!("release: {:?}", path);
         Ok(())
     }
 
-    fn flush(&self, _req: RequestInfo, path: &Path, fh: u64, _lock_owner: u64) -> ResultEmpty {
-        println!("flush: {:?}", path);
-        Ok(())
+    fn flush(&self, req: RequestInfo, _path: &Path, fh: u64, _lock_owner: u64) -> ResultEmpty {
+        self.flush_file(&req, fh)
     }
 
+    fn release(
+        &self,
+        req: RequestInfo,
+        _path: &Path,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+    ) -> ResultEmpty {
+        let ret = self.flush_file(&req, fh);
+
+        self.open_files.lock().unwrap().remove(&fh);
+
+        ret
+    }
+
+    /*
+
     fn fsync(&self, _req: RequestInfo, path: &Path, fh: u64, datasync: bool) -> ResultEmpty {
         println!("fsync: {:?}, data={:?}", path, datasync);
         Err(1)
@@ -771,19 +1787,33 @@ impl<'a> FilesystemMT for FuseableWrapper<'a> {
     }
     */
 
-    /*
     fn listxattr(&self, _req: RequestInfo, path: &Path, size: u32) -> ResultXattr {
-        println!("listxattr: {:?}", path);
-        Err(1)
+        let names: Vec<u8> = self
+            .xattrs(&path.to_string_lossy())?
+            .into_iter()
+            .flat_map(|(name, _)| format!("{}{}\0", XATTR_PREFIX, name).into_bytes())
+            .collect();
+
+        xattr_reply(names, size)
     }
-    */
 
-    /*
     fn getxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, size: u32) -> ResultXattr {
-        println!("getxattr: {:?} {:?} {}", path, name, size);
-        Err(1)
+        let name = name.to_string_lossy();
+        let name = if name.starts_with(XATTR_PREFIX) {
+            &name[XATTR_PREFIX.len()..]
+        } else {
+            return Err(libc::ENODATA);
+        };
+
+        let value = self
+            .xattrs(&path.to_string_lossy())?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.into_bytes())
+            .ok_or(libc::ENODATA)?;
+
+        xattr_reply(value, size)
     }
-    */
 
     /*
     fn setxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, value: &[u8], flags: u32, position: u32) -> ResultEmpty {
@@ -797,3 +1827,87 @@ impl<'a> FilesystemMT for FuseableWrapper<'a> {
     }
     */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{errno, Either, Fuseable, Permissions, Request};
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn mode_test() {
+        assert_eq!(Permissions::default().mode(false), 0o666);
+        assert_eq!(Permissions::access(true, false).mode(false), 0o444);
+        assert_eq!(Permissions::access(false, true).mode(false), 0o222);
+        assert_eq!(Permissions::owned(Some(1000), None).mode(false), 0o664);
+        assert_eq!(Permissions::default().mode(true), 0o777);
+        assert_eq!(Permissions::owned(None, Some(100)).mode(true), 0o755);
+    }
+
+    #[test]
+    fn allows_test() {
+        let open = Permissions::default();
+        assert!(open.allows(1000, &[1000], true, true));
+
+        let read_only = Permissions::access(true, false);
+        assert!(read_only.allows(1000, &[1000], true, false));
+        assert!(!read_only.allows(1000, &[1000], false, true));
+        assert!(!read_only.allows(0, &[0], false, true));
+
+        let owned = Permissions { uid: Some(1000), gid: Some(100), ..Permissions::default() };
+        assert!(owned.allows(1000, &[1000], true, true));
+        assert!(owned.allows(1001, &[1001, 100], true, true));
+        assert!(owned.allows(0, &[0], true, true));
+        assert!(owned.allows(1001, &[1001], true, false));
+        assert!(!owned.allows(1001, &[1001], false, true));
+    }
+
+    #[test]
+    fn below_test() {
+        let parent = Permissions { uid: Some(1000), gid: Some(100), ..Permissions::default() };
+        let child = Permissions { uid: Some(1001), ..Permissions::access(true, false) };
+
+        assert_eq!(
+            child.below(parent),
+            Permissions { readable: true, writable: false, uid: Some(1001), gid: Some(100) }
+        );
+        assert_eq!(
+            Permissions::default().below(Permissions::access(false, true)),
+            Permissions::access(false, true)
+        );
+    }
+
+    #[test]
+    fn check_deadline_test() {
+        assert!(Request::default().check_deadline().is_ok());
+
+        let later = Request {
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            ..Request::default()
+        };
+        assert!(later.check_deadline().is_ok());
+
+        let passed = Request {
+            deadline: Some(Instant::now() - Duration::from_millis(1)),
+            ..Request::default()
+        };
+        assert_eq!(errno(&passed.check_deadline().unwrap_err()), libc::ETIMEDOUT);
+    }
+
+    // nodes that don't care about the request only implement is_dir, read and write
+    #[test]
+    fn default_for_test() {
+        let request = Request { uid: Some(1000), gid: Some(1000), ..Request::default() };
+        let mut value = 1u32;
+
+        assert_eq!(value.is_dir_for(&request, &mut std::iter::empty()).unwrap(), false);
+
+        value.write_for(&request, &mut std::iter::empty(), b"2".to_vec()).unwrap();
+        assert_eq!(value, 2);
+
+        match value.read_for(&request, &mut std::iter::empty()).unwrap() {
+            Either::Right(content) => assert_eq!(content, "2"),
+            Either::Left(entries) => panic!("expected the content, got the entries {:?}", entries),
+        }
+    }
+}
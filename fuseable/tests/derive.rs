@@ -0,0 +1,178 @@
+use fuseable::{Either, Fuseable};
+use fuseable_derive::Fuseable;
+use pretty_assertions::assert_eq;
+
+// files are read as their content, directories as their entries separated by
+// spaces
+fn read<T: Fuseable>(node: &T, path: &str) -> String {
+    match node.read(&mut path.split_terminator('/')).unwrap() {
+        Either::Left(entries) => entries.join(" "),
+        Either::Right(content) => content,
+    }
+}
+
+fn write<T: Fuseable>(node: &mut T, path: &str, value: &str) -> fuseable::Result<()> {
+    node.write(&mut path.split_terminator('/'), value.as_bytes().to_vec())
+}
+
+#[derive(Debug, PartialEq, Fuseable)]
+enum Mode {
+    #[fuseable(rename = "off")]
+    Off,
+    Window {
+        start: u32,
+        end: u32,
+    },
+    #[fuseable(no_default)]
+    Fixed {
+        level: u8,
+        bits: u8,
+    },
+}
+
+#[test]
+fn enum_switch_test() {
+    let mut mode = Mode::Off;
+    assert_eq!(read(&mode, "variant"), "[off] Window Fixed");
+
+    write(&mut mode, "variant", "Window\n").unwrap();
+    assert_eq!(mode, Mode::Window { start: 0, end: 0 });
+    assert_eq!(read(&mode, ""), "start end variant");
+    assert_eq!(read(&mode, "variant"), "off [Window] Fixed");
+
+    // switching to the current variant keeps its fields
+    write(&mut mode, "end", "10").unwrap();
+    write(&mut mode, "variant", "Window").unwrap();
+    assert_eq!(mode, Mode::Window { start: 0, end: 10 });
+
+    assert!(write(&mut mode, "variant", "Fixed").is_err());
+    assert!(write(&mut mode, "variant", "Other").is_err());
+    assert_eq!(mode, Mode::Window { start: 0, end: 10 });
+
+    write(&mut mode, "variant", "off").unwrap();
+    assert_eq!(mode, Mode::Off);
+}
+
+#[derive(Debug, PartialEq, Fuseable)]
+struct Pair(u32, String);
+
+#[derive(Debug, PartialEq, Fuseable)]
+struct Wrapper<T>(T);
+
+#[derive(Debug, PartialEq, Fuseable)]
+struct Generic<T, U>
+where
+    U: Clone,
+{
+    value: T,
+    #[fuseable(ro)]
+    other: U,
+}
+
+#[derive(Debug, PartialEq, Fuseable)]
+enum Shape {
+    Point(u32, u32),
+    Size(u32),
+}
+
+#[test]
+fn tuple_struct_test() {
+    let mut pair = Pair(1, "one".to_owned());
+    assert_eq!(read(&pair, ""), "0 1");
+    assert_eq!(read(&pair, "1"), "one");
+
+    write(&mut pair, "0", "2").unwrap();
+    assert_eq!(pair, Pair(2, "one".to_owned()));
+}
+
+#[test]
+fn newtype_test() {
+    let mut wrapper = Wrapper(1u32);
+    assert_eq!(read(&wrapper, ""), "1");
+    assert_eq!(wrapper.is_dir(&mut std::iter::empty()).unwrap(), false);
+
+    write(&mut wrapper, "", "2").unwrap();
+    assert_eq!(wrapper, Wrapper(2));
+
+    // newtypes are transparent, also for directories
+    let mut wrapper = Wrapper(Pair(1, "one".to_owned()));
+    assert_eq!(read(&wrapper, ""), "0 1");
+    write(&mut wrapper, "1", "two").unwrap();
+    assert_eq!(wrapper, Wrapper(Pair(1, "two".to_owned())));
+}
+
+#[test]
+fn generic_test() {
+    let mut generic = Generic { value: Wrapper(1u8), other: 2u16 };
+    assert_eq!(read(&generic, ""), "value other");
+    assert_eq!(read(&generic, "other"), "2");
+
+    write(&mut generic, "value", "3").unwrap();
+    assert!(write(&mut generic, "other", "4").is_err());
+    assert_eq!(generic, Generic { value: Wrapper(3), other: 2 });
+}
+
+#[test]
+fn tuple_variant_test() {
+    let mut shape = Shape::Point(1, 2);
+    assert_eq!(read(&shape, ""), "0 1 variant");
+    assert_eq!(read(&shape, "1"), "2");
+
+    write(&mut shape, "0", "3").unwrap();
+    assert_eq!(shape, Shape::Point(3, 2));
+
+    // single field variants are transparent
+    let mut shape = Shape::Size(1);
+    assert_eq!(read(&shape, ""), "1");
+    write(&mut shape, "", "2").unwrap();
+    assert_eq!(shape, Shape::Size(2));
+
+    write(&mut shape, "variant", "Point").unwrap();
+    assert_eq!(shape, Shape::Point(0, 0));
+}
+
+#[derive(Debug, Default, PartialEq, Fuseable)]
+struct Inner {
+    a: u32,
+    #[fuseable(ro)]
+    b: u32,
+}
+
+#[derive(Debug, Default, PartialEq, Fuseable)]
+struct Attributes {
+    /// the renamed
+    /// field
+    #[fuseable(rename = "renamed")]
+    original: u32,
+    #[fuseable(flatten)]
+    inner: Inner,
+    #[fuseable(hidden)]
+    secret: u32,
+}
+
+#[test]
+fn field_attributes_test() {
+    let mut node = Attributes::default();
+    assert_eq!(read(&node, ""), "renamed a b");
+
+    write(&mut node, "renamed", "1").unwrap();
+    assert!(write(&mut node, "original", "2").is_err());
+    assert_eq!(node.original, 1);
+
+    // flattened entries are found by their own name
+    write(&mut node, "a", "3").unwrap();
+    assert!(write(&mut node, "b", "4").is_err());
+    assert!(read(&node, "").split(' ').all(|entry| entry != "inner"));
+    assert!(node.read(&mut "inner".split_terminator('/')).is_err());
+    assert_eq!(node.inner, Inner { a: 3, b: 0 });
+
+    // hidden fields are not listed, but accessible
+    write(&mut node, "secret", "5").unwrap();
+    assert_eq!(read(&node, "secret"), "5");
+
+    assert_eq!(
+        node.xattrs(&mut "renamed".split_terminator('/')).unwrap(),
+        vec![("doc".to_owned(), "the renamed field".to_owned())]
+    );
+    assert_eq!(node.xattrs(&mut "a".split_terminator('/')).unwrap(), vec![]);
+}
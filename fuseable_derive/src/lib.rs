@@ -7,52 +7,93 @@ use proc_macro2::{Ident, Span, TokenStream};
 use quote::quote;
 use syn::{
     parse_macro_input,
-    Data::{Enum, Struct},
+    Data::{Enum, Struct, Union},
     DeriveInput,
-    Field,
 };
 
+// the bodies of is_dir, read, write, permissions and xattrs
+type Impls = (TokenStream, TokenStream, TokenStream, TokenStream, TokenStream);
+
 #[proc_macro_derive(Fuseable, attributes(fuseable))]
 pub fn fuse_derive(input: TS) -> TS {
     let ast = parse_macro_input!(input as DeriveInput);
-    let gen = impl_fuseable(&ast);
+    let gen = impl_fuseable(&ast).unwrap_or_else(|e| e.to_compile_error());
     // println!("{}", gen);
     gen.into()
 }
 
-fn impl_fuseable(ast: &syn::DeriveInput) -> TokenStream {
+// every type parameter needs to be Fuseable, as the fields using them are
+// accessed through Fuseable
+fn with_fuseable_bounds(generics: &syn::Generics) -> syn::Generics {
+    let mut generics = generics.clone();
+    let params: Vec<_> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+
+    for param in params {
+        where_clause.predicates.push(syn::parse_quote! { #param: Fuseable });
+    }
+
+    generics
+}
+
+fn impl_fuseable(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
     let name = &ast.ident;
-    let (is_dir, read, write) = impl_body(ast);
+    let ((is_dir, read, write, permissions, xattrs), context_fields) = impl_body(ast)?;
+    let generics = with_fuseable_bounds(&ast.generics);
+    let context_impl = impl_context(name, &generics, &context_fields)?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     let dummy_const = Ident::new(&format!("_IMPL_FUSEABLE_FOR_{}", name), Span::call_site());
 
     let ret = quote! {
-        #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
+        #[allow(non_upper_case_globals, unused_attributes, unused_qualifications, unused_imports, unused_mut)]
         const #dummy_const: () = {
-            use fuseable::{Result, Fuseable, FuseableError, type_name};
+            use fuseable::{Result, Fuseable, FuseableContext, FuseableError, Permissions, Request, has_entry, type_name};
 
             #[allow(unused_variables)]
-            impl Fuseable for #name {
+            impl #impl_generics Fuseable for #name #ty_generics #where_clause {
 
                 fn is_dir(&self, path: &mut Iterator<Item = &str>) -> Result<bool> {
-                    #is_dir
+                    Fuseable::is_dir_for(self, &Request::default(), path)
                 }
 
                 fn read(&self, path: &mut Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
-                    #read
+                    Fuseable::read_for(self, &Request::default(), path)
                 }
 
                 fn write(&mut self, path: &mut Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+                    Fuseable::write_for(self, &Request::default(), path, value)
+                }
+
+                fn is_dir_for(&self, request: &Request, path: &mut Iterator<Item = &str>) -> Result<bool> {
+                    #is_dir
+                }
+
+                fn read_for(&self, request: &Request, path: &mut Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+                    #read
+                }
+
+                fn write_for(&mut self, request: &Request, path: &mut Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
                     #write
                 }
+
+                fn permissions(&self, path: &mut Iterator<Item = &str>) -> Result<Permissions> {
+                    #permissions
+                }
+
+                fn xattrs(&self, path: &mut Iterator<Item = &str>) -> Result<Vec<(String, String)>> {
+                    #xattrs
+                }
             }
+
+            #context_impl
         };
     };
 
 
     // println!("{}", ret);
 
-    ret
+    Ok(ret)
 }
 
 #[derive(Debug, Clone)]
@@ -61,15 +102,22 @@ enum IsDirImpl {
     Static(syn::LitBool),
 }
 
+// a field that is not backed by a struct member, but by the functions given for
+// is_dir, read and write. if a context type is given, these functions get the
+// context as their last argument (read and write also get the request after it)
+// and the field is only accessible using FuseableContext
 #[derive(Debug)]
 struct VirtualField {
     name: Ident,
     is_dir: IsDirImpl,
     read: TokenStream,
     write: TokenStream,
+    context: Option<TokenStream>,
 }
 
-fn impl_body(ast: &syn::DeriveInput) -> (TokenStream, TokenStream, TokenStream) {
+// the virtual fields with a context are returned separately, as they are not
+// part of the Fuseable implementation
+fn impl_body(ast: &syn::DeriveInput) -> syn::Result<(Impls, Vec<VirtualField>)> {
     let attrs: Vec<_> = ast
         .attrs
         .iter()
@@ -85,18 +133,17 @@ fn impl_body(ast: &syn::DeriveInput) -> (TokenStream, TokenStream, TokenStream)
 
     let mut virtual_fields = Vec::new();
 
-    fn lit_to_direct_string(lit: &syn::Lit) -> String {
+    fn lit_to_direct_string(lit: &syn::Lit) -> syn::Result<String> {
         match lit {
-            syn::Lit::Str(str) => str.value(),
-            _ => panic!("could not convert literal to string: {:?}", lit),
+            syn::Lit::Str(str) => Ok(str.value()),
+            _ => Err(syn::Error::new_spanned(lit, "expected a string literal")),
         }
     }
 
-    fn lit_to_ident(lit: &syn::Lit) -> syn::Ident {
-        let string = lit_to_direct_string(lit);
-        let tokens = syn::parse_str(&string).unwrap();
+    fn lit_to_ident(lit: &syn::Lit) -> syn::Result<syn::Ident> {
+        let tokens = lit_to_token_stream(lit)?;
 
-        syn::parse2(tokens).unwrap()
+        syn::parse2(tokens).map_err(|e| syn::Error::new_spanned(lit, e))
     }
 
     /*
@@ -108,54 +155,73 @@ fn impl_body(ast: &syn::DeriveInput) -> (TokenStream, TokenStream, TokenStream)
     }
     */
 
-    fn lit_to_token_stream(lit: &syn::Lit) -> TokenStream {
-        let string = lit_to_direct_string(lit);
-        syn::parse_str(&string).unwrap()
+    fn lit_to_token_stream(lit: &syn::Lit) -> syn::Result<TokenStream> {
+        let string = lit_to_direct_string(lit)?;
+        syn::parse_str(&string).map_err(|e| syn::Error::new_spanned(lit, e))
     }
 
     for attr in &attrs {
         if let Some(syn::Meta::List(syn::MetaList { nested, .. })) = &attr.interpret_meta() {
             for nested_meta in nested {
                 match nested_meta {
-                    Meta(List(MetaList { nested, ident, .. })) => {
-                        if ident == "virtual_field" {
-                            let mut name = None;
-                            let mut is_dir = None;
-                            let mut read = None;
-                            let mut write = None;
-
-                            for nested_meta in nested {
-                                if let Meta(NameValue(MetaNameValue { ident, lit, .. })) =
-                                    nested_meta
-                                {
+                    Meta(List(MetaList { nested, ident, .. })) if ident == "virtual_field" => {
+                        let mut name = None;
+                        let mut is_dir = None;
+                        let mut read = None;
+                        let mut write = None;
+                        let mut context = None;
+
+                        for nested_meta in nested {
+                            match nested_meta {
+                                Meta(NameValue(MetaNameValue { ident, lit, .. })) => {
                                     if ident == "name" {
-                                        name = Some(lit_to_ident(lit))
+                                        name = Some(lit_to_ident(lit)?)
                                     } else if ident == "is_dir" {
-                                        is_dir = Some(lit_to_token_stream(lit))
+                                        is_dir = Some(lit_to_token_stream(lit)?)
                                     } else if ident == "read" {
-                                        read = Some(lit_to_token_stream(lit))
+                                        read = Some(lit_to_token_stream(lit)?)
                                     } else if ident == "write" {
-                                        write = Some(lit_to_token_stream(lit))
+                                        write = Some(lit_to_token_stream(lit)?)
+                                    } else if ident == "context" {
+                                        context = Some(lit_to_token_stream(lit)?)
+                                    } else {
+                                        return Err(syn::Error::new_spanned(
+                                            ident,
+                                            "unknown virtual_field attribute",
+                                        ));
                                     }
                                 }
+                                _ => {
+                                    return Err(syn::Error::new_spanned(
+                                        nested_meta,
+                                        "expected name = \"...\"",
+                                    ))
+                                }
                             }
+                        }
 
-                            let name = name.unwrap();
-                            let is_dir = is_dir.unwrap();
-                            let read = read.unwrap();
-                            let write = write.unwrap();
+                        let missing = |what| {
+                            syn::Error::new(ident.span(), format!("virtual_field needs {}", what))
+                        };
 
-                            let is_dir = match syn::parse2::<syn::LitBool>(is_dir.clone()) {
-                                Ok(lit) => IsDirImpl::Static(lit),
-                                Err(_) => IsDirImpl::FunctionCall(is_dir),
-                            };
+                        let name = name.ok_or_else(|| missing("name"))?;
+                        let is_dir = is_dir.ok_or_else(|| missing("is_dir"))?;
+                        let read = read.ok_or_else(|| missing("read"))?;
+                        let write = write.ok_or_else(|| missing("write"))?;
 
-                            virtual_fields.push(VirtualField { name, is_dir, read, write });
-                        } else {
-                            panic!("unhandled meta {:?}", attrs)
-                        }
+                        let is_dir = match syn::parse2::<syn::LitBool>(is_dir.clone()) {
+                            Ok(lit) => IsDirImpl::Static(lit),
+                            Err(_) => IsDirImpl::FunctionCall(is_dir),
+                        };
+
+                        virtual_fields.push(VirtualField { name, is_dir, read, write, context });
+                    }
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            nested_meta,
+                            "unknown fuseable attribute",
+                        ))
                     }
-                    _ => panic!("unhandled meta {:?}", nested_meta),
                 }
             }
         }
@@ -173,98 +239,405 @@ fn impl_body(ast: &syn::DeriveInput) -> (TokenStream, TokenStream, TokenStream)
 
     // &attr.interpret_meta()
 
-    match ast.data {
-        Struct(ref data) => impl_struct(data, &virtual_fields),
+    let (context_fields, virtual_fields): (Vec<_>, Vec<_>) =
+        virtual_fields.into_iter().partition(|f| f.context.is_some());
+
+    let impls = match ast.data {
+        Struct(ref data) => impl_struct(data, &virtual_fields)?,
         Enum(ref data) => {
-            if !virtual_fields.is_empty() {
-                panic!("cannot handle virtual fields in enums yet");
+            if !virtual_fields.is_empty() || !context_fields.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    &ast.ident,
+                    "cannot handle virtual fields in enums yet",
+                ));
             }
 
-            impl_enum(&ast.ident, data)
+            impl_enum(&ast.ident, data)?
         }
-        _ => unimplemented!(),
-    }
+        Union(_) => return Err(syn::Error::new_spanned(&ast.ident, "unions are not supported")),
+    };
+
+    Ok((impls, context_fields))
 }
 
-fn impl_struct(
-    data: &syn::DataStruct,
+fn impl_context(
+    name: &Ident,
+    generics: &syn::Generics,
     virtual_fields: &[VirtualField],
-) -> (TokenStream, TokenStream, TokenStream) {
-    let (is_dir, read, write) = match data.fields {
-        syn::Fields::Named(ref fields) => {
-            /*
-            let fields_normal: Vec<_> = fields
-                .named
-                .iter()
-                .map(|f| &f.ident)
-                .map(|f| quote! { #f })
-                .collect();
-            let wrapped_fields: Vec<_> = fields
-                .named
-                .iter()
-                .map(|f| &f.ident)
-                .map(|f| quote! { &self.#f })
-                .collect();
-            */
+) -> syn::Result<TokenStream> {
+    let context = match virtual_fields.first() {
+        Some(field) => field.context.clone().unwrap(),
+        None => return Ok(quote! {}),
+    };
+
+    let context_of = |f: &VirtualField| f.context.as_ref().map(|c| c.to_string());
+
+    if let Some(field) = virtual_fields.iter().find(|f| context_of(f) != Some(context.to_string()))
+    {
+        return Err(syn::Error::new_spanned(
+            &field.name,
+            format!("all virtual fields of {} need to have the same context", name),
+        ));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let names_is_dir: Vec<_> = virtual_fields.iter().map(|f| f.name.clone()).collect();
+    let names_read = names_is_dir.clone();
+    let names_list = names_is_dir.clone();
+    let names_write = names_is_dir.clone();
+
+    let is_dirs: Vec<_> = virtual_fields
+        .iter()
+        .map(|f| match f.is_dir {
+            IsDirImpl::Static(ref lit) => quote! { Ok(#lit) },
+            IsDirImpl::FunctionCall(ref stream) => quote! { #stream(path, context) },
+        })
+        .collect();
+    let reads: Vec<_> = virtual_fields.iter().map(|f| f.read.clone()).collect();
+    let writes: Vec<_> = virtual_fields.iter().map(|f| f.write.clone()).collect();
+
+    Ok(quote! {
+        #[allow(unused_variables)]
+        impl #impl_generics FuseableContext<#context> for #name #ty_generics #where_clause {
+            fn is_dir_with(&self, context: &#context, request: &Request, path: &mut Iterator<Item = &str>) -> Result<bool> {
+                match path.next() {
+                    Some(name) => match name {
+                        #(stringify!(#names_is_dir) => #is_dirs, )*
+                        _ => Fuseable::is_dir_for(self, request, &mut std::iter::once(name).chain(path)),
+                    },
+                    None => Ok(true),
+                }
+            }
+
+            fn read_with(&self, context: &#context, request: &Request, path: &mut Iterator<Item = &str>) -> Result<Either<Vec<String>, String>> {
+                match path.next() {
+                    Some(name) => match name {
+                        #(stringify!(#names_read) => #reads(path, context, request), )*
+                        _ => Fuseable::read_for(self, request, &mut std::iter::once(name).chain(path)),
+                    },
+                    None => {
+                        let mut entries = match Fuseable::read_for(self, request, path)? {
+                            Either::Left(entries) => entries,
+                            Either::Right(_) => Vec::new(),
+                        };
+
+                        #(entries.push(stringify!(#names_list).to_owned());)*
+
+                        Ok(Either::Left(entries))
+                    }
+                }
+            }
 
-            let read_prefix = quote! { &self. };
-            let write_prefix = quote! { &mut self. };
+            fn write_with(&self, context: &#context, request: &Request, path: &mut Iterator<Item = &str>, value: Vec<u8>) -> Result<()> {
+                match path.next() {
+                    Some(name) => match name {
+                        #(stringify!(#names_write) => #writes(path, value, context, request), )*
+                        _ => Err(FuseableError::unsupported("write", name)),
+                    },
+                    None => Err(FuseableError::unsupported("write", type_name(&self))),
+                }
+            }
+        }
+    })
+}
+
+fn impl_struct(data: &syn::DataStruct, virtual_fields: &[VirtualField]) -> syn::Result<Impls> {
+    let fields = parse_fields(&data.fields)?;
 
-            // println!("implementing virtual fields {:?}", virtual_fields);
-            impl_fields(
-                &fields.named.iter().collect::<Vec<_>>(),
-                &read_prefix,
-                &write_prefix,
-                virtual_fields,
-            )
+    let read_prefix = quote! { &self. };
+    let write_prefix = quote! { &mut self. };
+
+    // println!("implementing virtual fields {:?}", virtual_fields);
+    match data.fields {
+        // newtypes are transparent, unless they need a directory for virtual fields
+        syn::Fields::Unnamed(_)
+            if fields.len() == 1 && !fields[0].skip && virtual_fields.is_empty() =>
+        {
+            Ok(impl_transparent(&fields[0], &read_prefix, &write_prefix))
         }
-        _ => unimplemented!(),
+        _ => Ok(impl_fields(&fields, &read_prefix, &write_prefix, virtual_fields)),
+    }
+}
+
+// forwards everything to the only field
+fn impl_transparent(
+    field: &ParsedField,
+    prefix_read: &TokenStream,
+    prefix_write: &TokenStream,
+) -> Impls {
+    let member = &field.member;
+    let (readable, writable) = (field.readable, field.writable);
+
+    let is_dir = quote! {
+        Fuseable::is_dir_for(#prefix_read #member, request, path)
+    };
+
+    let read = if readable {
+        quote! { Fuseable::read_for(#prefix_read #member, request, path) }
+    } else {
+        quote! { Err(FuseableError::unsupported("read", type_name(&self))) }
     };
 
-    (is_dir, read, write)
+    let write = if writable {
+        quote! { Fuseable::write_for(#prefix_write #member, request, path, value) }
+    } else {
+        quote! { Err(FuseableError::unsupported("write", type_name(&self))) }
+    };
+
+    let permissions = quote! {
+        Ok(Fuseable::permissions(#prefix_read #member, path)?.below(Permissions::access(#readable, #writable)))
+    };
+
+    let xattrs = quote! {
+        Fuseable::xattrs(#prefix_read #member, path)
+    };
+
+    (is_dir, read, write, permissions, xattrs)
 }
 
-fn impl_enum(name: &Ident, data: &syn::DataEnum) -> (TokenStream, TokenStream, TokenStream) {
-    let variants: Vec<_> = data.variants.iter().map(impl_enum_variant).collect();
+fn impl_enum(name: &Ident, data: &syn::DataEnum) -> syn::Result<Impls> {
+    let (variant_list, switch) = impl_enum_switch(data)?;
+
+    let variants = data
+        .variants
+        .iter()
+        .map(|v| impl_enum_variant(v, &switch))
+        .collect::<syn::Result<Vec<_>>>()?;
     let variant_names_read: Vec<_> = data.variants.iter().map(|v| &v.ident).collect();
     let variant_names_is_dir: Vec<_> = variant_names_read.clone();
     let variant_names_write: Vec<_> = variant_names_read.clone();
+    let variant_names_permissions: Vec<_> = variant_names_read.clone();
+    let variant_names_xattrs: Vec<_> = variant_names_read.clone();
 
     let is_dir: Vec<_> = variants.iter().map(|v| &v.0).collect();
     let read: Vec<_> = variants.iter().map(|v| &v.1).collect();
     let write: Vec<_> = variants.iter().map(|v| &v.2).collect();
+    let permissions: Vec<_> = variants.iter().map(|v| &v.3).collect();
+    let xattrs: Vec<_> = variants.iter().map(|v| &v.4).collect();
 
+    // every enum has a variant file next to the entries of the current variant,
+    // which is listed if the current variant is a directory
     let is_dir = quote! {
         use self::#name::{#(#variant_names_is_dir),*};
 
+        let path = &mut path.peekable();
+
+        match path.peek() {
+            Some(&"variant") => {
+                path.next();
+                match path.next() {
+                    Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+                    None => Ok(false),
+                }
+            }
+            _ => match self {
+                #(#is_dir, )*
+            }
+        }
+    };
+
+    let read_current = quote! {
         match self {
-            #(#is_dir, )*
+            #(#read, )*
         }
     };
 
     let read = quote! {
         use self::#name::{#(#variant_names_read),*};
 
-        match self {
-            #(#read, )*
+        let path = &mut path.peekable();
+
+        match path.peek() {
+            Some(&"variant") => {
+                path.next();
+                match path.next() {
+                    Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+                    None => Ok(Either::Right(#variant_list)),
+                }
+            }
+            Some(_) => #read_current,
+            None => #read_current.map(|value| match value {
+                Either::Left(mut entries) => {
+                    entries.push("variant".to_owned());
+                    Either::Left(entries)
+                }
+                file => file,
+            }),
         }
     };
 
     let write = quote! {
         use self::#name::{#(#variant_names_write),*};
 
+        let path = &mut path.peekable();
+
+        match path.peek() {
+            Some(&"variant") => {
+                path.next();
+                match path.next() {
+                    Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+                    None => #switch,
+                }
+            }
+            _ => match self {
+                #(#write, )*
+            }
+        }
+    };
+
+    let permissions = quote! {
+        use self::#name::{#(#variant_names_permissions),*};
+
+        let path = &mut path.peekable();
+
+        match path.peek() {
+            Some(&"variant") => Ok(Permissions::default()),
+            _ => match self {
+                #(#permissions, )*
+            }
+        }
+    };
+
+    let xattrs = quote! {
+        use self::#name::{#(#variant_names_xattrs),*};
+
+        let path = &mut path.peekable();
+
+        match path.peek() {
+            Some(&"variant") => Ok(Vec::new()),
+            _ => match self {
+                #(#xattrs, )*
+            }
+        }
+    };
+
+    Ok((is_dir, read, write, permissions, xattrs))
+}
+
+// the content of the variant file, all variants with the current one in
+// brackets, and the body switching to the variant named by value. variants are
+// named by their ident, unless they are renamed. the fields of the new variant
+// are set to their default, unless the variant is marked no_default, in which
+// case it can't be switched to
+fn impl_enum_switch(data: &syn::DataEnum) -> syn::Result<(TokenStream, TokenStream)> {
+    use syn::{
+        Meta::{List, NameValue},
+        MetaList,
+        MetaNameValue,
+        NestedMeta::Meta,
+    };
+
+    let mut names = Vec::new();
+    let mut switchable = Vec::new();
+    let mut fixed = Vec::new();
+
+    for variant in &data.variants {
+        let mut no_default = false;
+        let mut name = variant.ident.to_string();
+
+        for attr in &variant.attrs {
+            if let Some(List(MetaList { ref ident, ref nested, .. })) = attr.interpret_meta() {
+                if ident != "fuseable" {
+                    continue;
+                }
+
+                for nested_meta in nested {
+                    match nested_meta {
+                        Meta(syn::Meta::Word(ident)) if ident == "no_default" => no_default = true,
+                        Meta(NameValue(MetaNameValue {
+                            ident, lit: syn::Lit::Str(str), ..
+                        })) if ident == "rename" => name = str.value(),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                nested_meta,
+                                "unknown fuseable attribute",
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+
+        names.push(name.clone());
+
+        if no_default {
+            fixed.push(name);
+        } else {
+            switchable.push((variant, name));
+        }
+    }
+
+    let current_names = names.clone();
+    let current_variants: Vec<_> = data.variants.iter().map(|v| &v.ident).collect();
+    let current = quote! {
         match self {
-            #(#write, )*
+            #(#current_variants { .. } => #current_names, )*
+        }
+    };
+
+    let variant_list = quote! {
+        {
+            let current = #current;
+            let variants: Vec<_> = vec![#(#names),*]
+                .into_iter()
+                .map(|v| if v == current { format!("[{}]", v) } else { v.to_owned() })
+                .collect();
+
+            variants.join(" ")
         }
     };
 
-    (is_dir, read, write)
+    let switchable_names: Vec<_> = switchable.iter().map(|(_, name)| name).collect();
+    let constructors: Vec<_> = switchable
+        .iter()
+        .map(|(v, _)| {
+            let ident = &v.ident;
+            let defaults = v.fields.iter().map(|f| match f.ident {
+                Some(ref field) => quote! { #field: Default::default() },
+                None => quote! { Default::default() },
+            });
+
+            match v.fields {
+                syn::Fields::Named(_) => quote! { #ident { #(#defaults),* } },
+                syn::Fields::Unnamed(_) => quote! { #ident(#(#defaults),*) },
+                syn::Fields::Unit => quote! { #ident },
+            }
+        })
+        .collect();
+    let fixed_names = fixed;
+
+    let switch = quote! {
+        {
+            let value = String::from_utf8(value)?;
+            let current = #current;
+
+            match value.trim() {
+                variant if variant == current => {}
+                #(#switchable_names => *self = #constructors, )*
+                #(variant @ #fixed_names => {
+                    return Err(FuseableError::unsupported("switching to", variant))
+                })*
+                variant => {
+                    return Err(FuseableError::invalid_value(format!(
+                        "{} is not a variant of {}",
+                        variant,
+                        type_name(&self)
+                    )))
+                }
+            }
+
+            Ok(())
+        }
+    };
+
+    Ok((variant_list, switch))
 }
 
-fn impl_enum_variant(variant: &syn::Variant) -> (TokenStream, TokenStream, TokenStream) {
+fn impl_enum_variant(variant: &syn::Variant, switch: &TokenStream) -> syn::Result<Impls> {
     let name = &variant.ident;
 
-    let (is_dir, read, write) = match variant.fields {
+    let (is_dir, read, write, permissions, xattrs) = match variant.fields {
         syn::Fields::Named(ref fields) => {
             let fields: Vec<_> = fields.named.iter().collect();
 
@@ -272,20 +645,20 @@ fn impl_enum_variant(variant: &syn::Variant) -> (TokenStream, TokenStream, Token
                 let name = fields[0].clone();
                 impl_enum_variant_flatten(&name, false)
             } else {
-                impl_enum_variant_namend(&fields)
+                impl_enum_variant_fields(&parse_fields(&variant.fields)?, false)
             }
         }
         syn::Fields::Unnamed(ref fields) => {
             let fields: Vec<_> = fields.unnamed.iter().collect();
             if fields.len() != 1 {
-                unimplemented!()
+                impl_enum_variant_fields(&parse_fields(&variant.fields)?, true)
             } else {
                 let mut field = fields[0].clone();
                 field.ident = Some(Ident::new("value_", Span::call_site()));
                 impl_enum_variant_flatten(&field, true)
             }
         }
-        syn::Fields::Unit => impl_enum_variant_unit(&name),
+        syn::Fields::Unit => impl_enum_variant_unit(&name, switch),
     };
 
     let is_dir = quote! {
@@ -300,14 +673,24 @@ fn impl_enum_variant(variant: &syn::Variant) -> (TokenStream, TokenStream, Token
         #name #write
     };
 
-    (is_dir, read, write)
+    let permissions = quote! {
+        #name #permissions
+    };
+
+    let xattrs = quote! {
+        #name #xattrs
+    };
+
+    Ok((is_dir, read, write, permissions, xattrs))
 }
 
-fn impl_enum_variant_unit(name: &syn::Ident) -> (TokenStream, TokenStream, TokenStream) {
+// unit variants are files containing their name, writing the name of another
+// variant switches to it
+fn impl_enum_variant_unit(name: &syn::Ident, switch: &TokenStream) -> Impls {
     let is_dir = quote! {
         => {
             match path.next() {
-                Some(s) => Err(FuseableError::not_a_directory(stringify!($t), s)),
+                Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
                 None => Ok(false)
             }
         }
@@ -316,25 +699,33 @@ fn impl_enum_variant_unit(name: &syn::Ident) -> (TokenStream, TokenStream, Token
     let read = quote! {
         => {
             match path.next() {
-                Some(s) => Err(FuseableError::not_a_directory(stringify!($t), s)),
-                None => Ok(stringify!(#name).to_owned())
+                Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+                None => Ok(Either::Right(stringify!(#name).to_owned()))
             }
         }
     };
 
     let write = quote! {
         => {
-            None => Err(FuseableError::unsupported("write", type_name(&self))),
+            match path.next() {
+                Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+                None => #switch,
+            }
         }
     };
 
-    (is_dir, read, write)
+    let permissions = quote! {
+        => Ok(Permissions::default())
+    };
+
+    let xattrs = quote! {
+        => Ok(Vec::new())
+    };
+
+    (is_dir, read, write, permissions, xattrs)
 }
 
-fn impl_enum_variant_flatten(
-    name: &syn::Field,
-    unnamed: bool,
-) -> (TokenStream, TokenStream, TokenStream) {
+fn impl_enum_variant_flatten(name: &syn::Field, unnamed: bool) -> Impls {
     let name = name.ident.clone().unwrap();
     let wrapped_name = if unnamed {
         quote! {
@@ -357,230 +748,391 @@ fn impl_enum_variant_flatten(
     };
 
     let is_dir = quote! {
-        #wrapped_name => Fuseable::is_dir(#name, path)
+        #wrapped_name => Fuseable::is_dir_for(#name, request, path)
     };
 
     let read = quote! {
-        #wrapped_name => Fuseable::read(#name, path)
+        #wrapped_name => Fuseable::read_for(#name, request, path)
     };
 
     let write = quote! {
-        #wrapped_name_write => Fuseable::write(#name, path, value)
+        #wrapped_name_write => Fuseable::write_for(#name, request, path, value)
+    };
+
+    let permissions = quote! {
+        #wrapped_name => Fuseable::permissions(#name, path)
     };
 
-    (is_dir, read, write)
+    let xattrs = quote! {
+        #wrapped_name => Fuseable::xattrs(#name, path)
+    };
+
+    (is_dir, read, write, permissions, xattrs)
 }
 
-fn impl_enum_variant_namend(fields: &[&syn::Field]) -> (TokenStream, TokenStream, TokenStream) {
-    let fields_is_dir: Vec<_> = fields
+// the fields are bound by the pattern of the variant, the fields of tuple
+// variants as value_<index>
+fn impl_enum_variant_fields(fields: &[ParsedField], unnamed: bool) -> Impls {
+    let fields: Vec<_> = fields
         .iter()
-        .map(|f| {
-            let f = f.ident.clone().unwrap();
-            quote! { #f }
+        .cloned()
+        .enumerate()
+        .map(|(index, mut field)| {
+            if unnamed {
+                let binding = Ident::new(&format!("value_{}", index), Span::call_site());
+                field.member = quote! { #binding };
+            }
+
+            field
         })
         .collect();
 
-    /*
-    let fields_write: Vec<_> = fields.iter().map(|f| {
-        let f = f.ident.clone().unwrap();
-        quote! { ref mut #f }
-    }).collect();
-    */
-
-    let fields_read: Vec<_> = fields_is_dir.clone();
-    let fields_write: Vec<_> = fields_is_dir.clone();
+    let bindings: Vec<_> = fields.iter().map(|f| f.member.clone()).collect();
+    let pattern = if unnamed {
+        quote! { ( #(#bindings),* ) }
+    } else {
+        quote! { { #(#bindings),* } }
+    };
 
-    let (fields_impl_is_dir, fields_impl_read, fields_impl_write) =
-        impl_fields(&fields, &quote! {}, &quote! {}, &Vec::new());
+    let (
+        fields_impl_is_dir,
+        fields_impl_read,
+        fields_impl_write,
+        fields_impl_permissions,
+        fields_impl_xattrs,
+    ) = impl_fields(&fields, &quote! {}, &quote! {}, &Vec::new());
 
     let is_dir = quote! {
-        { #(#fields_is_dir),* } => {
+        #pattern => {
             #fields_impl_is_dir
         }
     };
 
     let read = quote! {
-        { #(#fields_read),* } => {
+        #pattern => {
             #fields_impl_read
         }
     };
 
     let write = quote! {
-        { #(#fields_write),* } => {
+        #pattern => {
             #fields_impl_write
         }
     };
 
-    (is_dir, read, write)
+    let permissions = quote! {
+        #pattern => {
+            #fields_impl_permissions
+        }
+    };
+
+    let xattrs = quote! {
+        #pattern => {
+            #fields_impl_xattrs
+        }
+    };
+
+    (is_dir, read, write, permissions, xattrs)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct ParsedField {
-    ident: Ident,
+    // how the field is accessed, its ident or the index for tuple structs
+    member: TokenStream,
+    // the name of the entry in the tree, the member unless it was renamed
+    name: String,
     skip: bool,
     writable: bool,
     readable: bool,
+    // the entries of the field are merged into the ones of its parent
+    flatten: bool,
+    // accessible, but not listed
+    hidden: bool,
+    // the doc comment of the field, exposed as its doc xattr
+    doc: Option<String>,
 }
 
-fn parse_field(field: &&syn::Field) -> ParsedField {
-    let ident = field.ident.clone().unwrap();
+fn parse_fields(fields: &syn::Fields) -> syn::Result<Vec<ParsedField>> {
+    fields.iter().enumerate().map(|(index, field)| parse_field(index, field)).collect()
+}
 
-    let attrs: Vec<_> = field
-        .attrs
-        .iter()
-        .filter(|a| a.path.segments.iter().map(|s| &s.ident).next().unwrap() == "fuseable")
-        .collect();
+fn parse_field(index: usize, field: &syn::Field) -> syn::Result<ParsedField> {
+    use syn::{
+        Meta::{List, NameValue, Word},
+        MetaList,
+        MetaNameValue,
+        NestedMeta::Meta,
+    };
+
+    let (member, mut name) = match field.ident {
+        Some(ref ident) => (quote! { #ident }, ident.to_string()),
+        None => {
+            let index = syn::Index::from(index);
+            (quote! { #index }, index.index.to_string())
+        }
+    };
 
     let mut skip = false;
     let mut writable = true;
     let mut readable = true;
-
-    for attr in attrs {
-        if let Some(syn::Meta::List(syn::MetaList { nested, .. })) = &attr.interpret_meta() {
-            if let Some(syn::NestedMeta::Meta(syn::Meta::Word(ident))) = nested.iter().next() {
-                if ident == "skip" {
-                    skip = true;
-                } else if ident == "ro" {
-                    writable = false;
-                } else if ident == "wo" {
-                    readable = false;
-                } else if ident == "rw" {
-                    readable = true;
-                    writable = true;
+    let mut flatten = false;
+    let mut hidden = false;
+    let mut doc = Vec::new();
+
+    for attr in &field.attrs {
+        match attr.interpret_meta() {
+            Some(NameValue(MetaNameValue { ref ident, lit: syn::Lit::Str(ref line), .. }))
+                if ident == "doc" =>
+            {
+                doc.push(line.value().trim().to_owned())
+            }
+            Some(List(MetaList { ref ident, ref nested, .. })) if ident == "fuseable" => {
+                for nested_meta in nested {
+                    match nested_meta {
+                        Meta(Word(ident)) => {
+                            if ident == "skip" {
+                                skip = true;
+                            } else if ident == "ro" {
+                                writable = false;
+                            } else if ident == "wo" {
+                                readable = false;
+                            } else if ident == "rw" {
+                                readable = true;
+                                writable = true;
+                            } else if ident == "flatten" {
+                                flatten = true;
+                            } else if ident == "hidden" {
+                                hidden = true;
+                            } else {
+                                return Err(syn::Error::new_spanned(
+                                    ident,
+                                    "unknown fuseable attribute",
+                                ));
+                            }
+                        }
+                        Meta(NameValue(MetaNameValue {
+                            ident, lit: syn::Lit::Str(str), ..
+                        })) if ident == "rename" => name = str.value(),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                nested_meta,
+                                "unknown fuseable attribute",
+                            ))
+                        }
+                    }
                 }
             }
+            _ => {}
         }
     }
 
-    ParsedField { ident, skip, readable, writable }
+    // only listed entries of a flattened field can be found, see has_entry
+    if flatten && hidden {
+        return Err(syn::Error::new_spanned(field, "flattened fields cannot be hidden"));
+    }
+
+    let doc = if doc.is_empty() { None } else { Some(doc.join(" ").trim().to_owned()) };
+
+    Ok(ParsedField { member, name, skip, readable, writable, flatten, hidden, doc })
     //    println!("parsed field {:#?}", field);
 }
 
 fn impl_fields(
-    fields: &[&Field],
+    fields: &[ParsedField],
     prefix_read: &TokenStream,
     prefix_write: &TokenStream,
     virtual_fields: &[VirtualField],
-) -> (TokenStream, TokenStream, TokenStream) {
-    let fields_read: Vec<_> = fields
-        .iter()
-        .map(parse_field)
-        .filter(|f| (!f.skip) && f.readable)
-        .map(|f| f.ident.clone())
-        .collect();
-    let fields_write: Vec<_> = fields
-        .iter()
-        .map(parse_field)
-        .filter(|f| (!f.skip) && f.writable)
-        .map(|f| f.ident.clone())
-        .collect();
-    let fields_is_dir = fields_read.clone();
+) -> Impls {
+    let fields: Vec<_> = fields.iter().filter(|f| !f.skip).collect();
 
-    let wrapped_fields_read: Vec<_> =
-        fields_read.iter().map(|f| quote! { #prefix_read #f }).collect();
-    let wrapped_fields_is_dir: Vec<_> =
-        fields_is_dir.iter().map(|f| quote! { #prefix_read #f }).collect();
-    let wrapped_fields_write: Vec<_> =
-        fields_write.iter().map(|f| quote! { #prefix_write #f }).collect();
+    let field_read = |f: &ParsedField| {
+        let member = &f.member;
+        quote! { #prefix_read #member }
+    };
+    let field_write = |f: &ParsedField| {
+        let member = &f.member;
+        quote! { #prefix_write #member }
+    };
 
-    let mut all_fields = fields_read.clone();
+    // flattened fields have no entry of their own, so their arms go last and match
+    // any name the field has an entry for, which is passed on as part of the path
+    let arms = |filter: &dyn Fn(&ParsedField) -> bool,
+                body: &dyn Fn(&ParsedField, TokenStream) -> TokenStream| {
+        let (flattened, direct): (Vec<_>, Vec<_>) =
+            fields.iter().cloned().filter(|f| filter(*f)).partition(|f| f.flatten);
+
+        let direct = direct.into_iter().map(|f| {
+            let name = &f.name;
+            let body = body(f, quote! { path });
+            quote! { #name => #body, }
+        });
+
+        let flattened = flattened.into_iter().map(|f| {
+            let field = field_read(f);
+            let body = body(f, quote! { &mut std::iter::once(name).chain(path) });
+            quote! { _ if has_entry(#field, name) => #body, }
+        });
+
+        (direct.collect::<Vec<_>>(), flattened.collect::<Vec<_>>())
+    };
 
-    let virtual_names_read: Vec<_> = virtual_fields.iter().map(|f| f.name.clone()).collect();
-    let virtual_names_is_dir = virtual_names_read.clone();
-    let virtual_names_write = virtual_names_read.clone();
+    let readable = |f: &ParsedField| f.readable;
+    let writable = |f: &ParsedField| f.writable;
+    let accessible = |f: &ParsedField| f.readable || f.writable;
 
-    let virtual_reads: Vec<_> = virtual_fields.iter().map(|f| f.read.clone()).collect();
-    let virtual_writes: Vec<_> = virtual_fields.iter().map(|f| f.write.clone()).collect();
-    let virtual_is_dirs: Vec<_> = virtual_fields.iter().map(|f| f.is_dir.clone()).collect();
+    let virtual_name = |f: &VirtualField| f.name.to_string();
 
-    all_fields.append(&mut virtual_names_read.clone());
+    let (read_arms, read_flattened) = arms(&readable, &|f, path| {
+        let field = field_read(f);
+        quote! { Fuseable::read_for(#field, request, #path) }
+    });
+    let virtual_read_arms: Vec<_> = virtual_fields
+        .iter()
+        .map(|f| {
+            let (name, read) = (virtual_name(f), &f.read);
+            quote! { #name => #read(path), }
+        })
+        .collect();
 
-    let read = if fields_read.len() + virtual_names_read.len() > 0 {
-        quote! {
-            match path.next() {
-                Some(ref name) => {
-                    match name.as_ref() {
-                        #(stringify!(#fields_read) => Fuseable::read(#wrapped_fields_read, path), )*
-                        #(stringify!(#virtual_names_read) => #virtual_reads(path), )*
-                        _ => Err(FuseableError::not_found(name)),
-                    }
-                }
-                None => Ok(Either::Left(vec![#(stringify!(#all_fields).to_string()),*]))
-            }
-        }
-    } else {
-        quote! {
-            match path.next() {
-                Some(ref name) => {
-                    Err(FuseableError::not_found(name))
-                },
-                None => Ok(Either::Left(vec![])),
+    let listed: Vec<_> = fields
+        .iter()
+        .filter(|f| f.readable && !f.hidden && !f.flatten)
+        .map(|f| f.name.clone())
+        .chain(virtual_fields.iter().map(virtual_name))
+        .collect();
+    let listed_flattened: Vec<_> =
+        fields.iter().cloned().filter(|f| f.readable && f.flatten).map(field_read).collect();
+
+    let read = quote! {
+        match path.next() {
+            Some(name) => match name {
+                #(#read_arms)*
+                #(#virtual_read_arms)*
+                #(#read_flattened)*
+                _ => Err(FuseableError::not_found(name)),
+            },
+            None => {
+                let mut entries: Vec<String> = vec![#(#listed.to_owned()),*];
+                #(entries.append(&mut fuseable::entries(#listed_flattened)?);)*
+                Ok(Either::Left(entries))
             }
         }
     };
 
-    let write = if fields_write.len() + virtual_names_write.len() > 0 {
-        quote! {
-            match path.next() {
-                Some(ref name) => {
-                    match name.as_ref() {
-                        #(stringify!(#fields_write) => Fuseable::write(#wrapped_fields_write, path, value), )*
-                        #(stringify!(#virtual_names_write) => #virtual_writes(path, value), )*
-                        _ => Err(FuseableError::not_found(name)),
-                    }
-                }
-                None => Err(FuseableError::unsupported("write", type_name(&self))),
-            }
-        }
-    } else {
-        quote! {
-            match path.next() {
-                Some(ref name) => {
-                    Err(FuseableError::not_found(name))
-                },
-                None => Err(FuseableError::unsupported("write", type_name(&self))),
-            }
+    let (write_arms, write_flattened) = arms(&writable, &|f, path| {
+        let field = field_write(f);
+        quote! { Fuseable::write_for(#field, request, #path, value) }
+    });
+    let virtual_write_arms: Vec<_> = virtual_fields
+        .iter()
+        .map(|f| {
+            let (name, write) = (virtual_name(f), &f.write);
+            quote! { #name => #write(path, value), }
+        })
+        .collect();
+
+    let write = quote! {
+        match path.next() {
+            Some(name) => match name {
+                #(#write_arms)*
+                #(#virtual_write_arms)*
+                #(#write_flattened)*
+                _ => Err(FuseableError::not_found(name)),
+            },
+            None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     };
 
-    let is_dirs_impls: Vec<_> = virtual_names_is_dir
+    let (is_dir_arms, is_dir_flattened) = arms(&readable, &|f, path| {
+        let field = field_read(f);
+        quote! { Fuseable::is_dir_for(#field, request, #path) }
+    });
+    let virtual_is_dir_arms: Vec<_> = virtual_fields
         .iter()
-        .zip(virtual_is_dirs)
-        .map(|(name, is_dir_impl)| match is_dir_impl {
-            IsDirImpl::Static(lit) => {
-                quote! {
-                    stringify!(#name) => Ok(#lit),
-                }
-            }
-            IsDirImpl::FunctionCall(stream) => {
-                quote! {
-                    stringify!(#name) => #stream(path),
-                }
+        .map(|f| {
+            let name = virtual_name(f);
+            match f.is_dir {
+                IsDirImpl::Static(ref lit) => quote! { #name => Ok(#lit), },
+                IsDirImpl::FunctionCall(ref stream) => quote! { #name => #stream(path), },
             }
         })
         .collect();
 
-    let is_dir = if fields_is_dir.len() + is_dirs_impls.len() > 0 {
-        quote! {
-            match path.next() {
-                Some(ref name) => {
-                    match name.as_ref() {
-                        #(stringify!(#fields_is_dir) => Fuseable::is_dir(#wrapped_fields_is_dir, path), )*
-                        #(#is_dirs_impls)*
-                        _ => Err(FuseableError::not_found(name)),
+    let is_dir = quote! {
+        match path.next() {
+            Some(name) => match name {
+                #(#is_dir_arms)*
+                #(#virtual_is_dir_arms)*
+                #(#is_dir_flattened)*
+                _ => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(true)
+        }
+    };
+
+    let (xattrs_arms, xattrs_flattened) = arms(&readable, &|f, path| {
+        let field = field_read(f);
+        match f.doc {
+            // the doc belongs to the field itself, not to the nodes below it
+            Some(ref doc) if !f.flatten => quote! {
+                {
+                    let path: Vec<_> = path.collect();
+                    let mut xattrs = Fuseable::xattrs(#field, &mut path.iter().cloned())?;
+                    if path.is_empty() {
+                        xattrs.push(("doc".to_owned(), #doc.to_owned()));
                     }
+                    Ok(xattrs)
                 }
-                None => Ok(true)
-            }
+            },
+            _ => quote! { Fuseable::xattrs(#field, #path) },
         }
-    } else {
+    });
+    let virtual_xattrs_arms: Vec<_> = virtual_fields
+        .iter()
+        .map(|f| {
+            let name = virtual_name(f);
+            quote! { #name => Ok(Vec::new()), }
+        })
+        .collect();
+
+    let xattrs = quote! {
+        match path.next() {
+            Some(name) => match name {
+                #(#xattrs_arms)*
+                #(#virtual_xattrs_arms)*
+                #(#xattrs_flattened)*
+                _ => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Vec::new())
+        }
+    };
+
+    let (permissions_arms, permissions_flattened) = arms(&accessible, &|f, path| {
+        let field = field_read(f);
+        let (readable, writable) = (f.readable, f.writable);
         quote! {
-            match path.next() {
-                Some(ref name) => Err(FuseableError::not_found(name)),
-                None => Ok(true)
-            }
+            Ok(Fuseable::permissions(#field, #path)?.below(Permissions::access(#readable, #writable)))
+        }
+    });
+    let virtual_permissions_arms: Vec<_> = virtual_fields
+        .iter()
+        .map(|f| {
+            let name = virtual_name(f);
+            quote! { #name => Ok(Permissions::default()), }
+        })
+        .collect();
+
+    let permissions = quote! {
+        match path.next() {
+            Some(name) => match name {
+                #(#permissions_arms)*
+                #(#virtual_permissions_arms)*
+                #(#permissions_flattened)*
+                _ => Err(FuseableError::not_found(name)),
+            },
+            None => Ok(Permissions::default())
         }
     };
 
-    (is_dir, read, write)
+    (is_dir, read, write, permissions, xattrs)
 }
@@ -24,6 +24,22 @@ pub struct Address {
     pub slice: Option<Slice>,
 }
 
+// the base in hex followed by the slice, for example 0x3040[14:15]
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "0x")?;
+
+        for byte in &self.base {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        match self.slice {
+            Some(Slice { start, end }) => write!(f, "[{}:{}]", start, end),
+            None => Ok(()),
+        }
+    }
+}
+
 impl Address {
     fn parse_internal(
         str: &str,
@@ -1,14 +1,19 @@
 use crate::address::{Address, Slice};
 
-// v is a vector of bytes in big endian order
+// v is a vector of bytes in big endian order, so bit 0 is the lowest bit of
+// its last byte
 // shift v by address.slice_start bits to the right
 // and accumulate address.slice_end - address.slice_start bits
-// padded to whole bytes with zeros
+// padded to whole bytes with zeros, the result is big endian as well
 // modifies v inplace (&mut [u8] would also do, but then we couldn't truncate)
 pub fn slice(mut v: Vec<u8>, address: &Address) -> Vec<u8> {
     match address.slice {
         None => v,
         Some(Slice { start: slice_start, end: slice_end }) => {
+            // the bits are counted from the lowest byte, which is easier with it
+            // at the start
+            v.reverse();
+
             let byte_offset = (slice_start >> 3) as usize;
             let bit_offset = (slice_start % 8) as usize;
             let bits = (slice_end - slice_start) as usize;
@@ -44,6 +49,7 @@ pub fn slice(mut v: Vec<u8>, address: &Address) -> Vec<u8> {
             v[write_idx] = (lower_half | upper_half) & (0xff >> (8 - end_bits));
 
             v.truncate(write_idx + 1);
+            v.reverse();
 
             v
         }
@@ -63,7 +69,8 @@ fn shift_right_with_overflow(val: u8, shift: usize) -> u8 {
 // write the contents of value to dest
 // starting from address.slice_start
 // stopping at address.slice_end bits
-pub fn slice_write(dest: &mut [u8], value: Vec<u8>, address: &Address) {
+// both are big endian, like the argument of slice
+pub fn slice_write(dest: &mut [u8], mut value: Vec<u8>, address: &Address) {
     assert!(
         address.slice.is_some(),
         "slice_write doesn't do anything if address doesn't contain a slice"
@@ -87,6 +94,9 @@ pub fn slice_write(dest: &mut [u8], value: Vec<u8>, address: &Address) {
             // because we wont always have a byte coming after that
             let complete_bytes = complete_bytes - 1;
 
+            dest.reverse();
+            value.reverse();
+
             let mut read_idx = 0;
 
             for i in byte_offset..(byte_offset + complete_bytes) {
@@ -116,6 +126,8 @@ pub fn slice_write(dest: &mut [u8], value: Vec<u8>, address: &Address) {
 
                 dest[i + 1] = masked_write(upper_half, dest[i + 1], upper_mask);
             };
+
+            dest.reverse();
         }
     }
 }
@@ -124,8 +136,29 @@ pub fn slice_write(dest: &mut [u8], value: Vec<u8>, address: &Address) {
 mod tests {
     use crate::{
         address::{Address, Slice},
-        bit_slice::slice_write,
+        bit_slice::{slice, slice_write},
     };
+    use pretty_assertions::assert_eq;
+
+    fn address(start: u8, end: u8) -> Address {
+        Address { base: vec![], slice: Some(Slice { start, end }) }
+    }
+
+    #[test]
+    fn byte_order_test() {
+        // analog_gain[12:14] is the mask 0x3000 of the register
+        assert_eq!(slice(vec![0x30, 0x01], &address(12, 14)), vec![0x03]);
+        assert_eq!(slice(vec![0x30, 0x01], &address(0, 4)), vec![0x01]);
+        assert_eq!(slice(vec![0xf7, 0xe3], &address(0, 12)), vec![0x07, 0xe3]);
+
+        let mut v = vec![0x00, 0x30];
+        slice_write(&mut v, vec![0x02], &address(12, 14));
+        assert_eq!(v, vec![0x20, 0x30]);
+
+        let mut v = vec![0xf0, 0x06];
+        slice_write(&mut v, vec![0x07, 0xe3], &address(0, 12));
+        assert_eq!(v, vec![0xf7, 0xe3]);
+    }
 
     #[test]
     fn dummy_test() {
@@ -0,0 +1,178 @@
+use failure::format_err;
+use fuseable::{Either, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+fn crc16_ccitt() -> u16 { 0x1021 }
+
+#[derive(Debug, Default, Serialize, Fuseable)]
+pub struct ChecksumState {
+    /// None until the checksum was synced with the device
+    #[fuseable(ro)]
+    expected: Option<u16>,
+    /// writes since the last check
+    #[fuseable(ro)]
+    writes: u64,
+    #[fuseable(ro)]
+    checks: u64,
+    #[fuseable(ro)]
+    mismatches: u64,
+    // the result of the last compare, exposed as checksum/check
+    #[fuseable(skip)]
+    last_check: Option<String>,
+}
+
+// running crc over all writes issued to a device, that is compared against a
+// checksum register of the device itself (for example i2c_wrt_checksum of the
+// ar0330) to detect dropped or corrupted writes
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+pub struct WriteChecksum {
+    /// the register of the register set containing the checksum of the device
+    #[fuseable(ro)]
+    pub register: String,
+    #[fuseable(ro)]
+    #[serde(default = "crc16_ccitt")]
+    poly: u16,
+    /// whether the register address is part of the checksum or only the data
+    #[fuseable(ro)]
+    #[serde(default)]
+    include_address: bool,
+    /// compare the checksum after this many writes, otherwise it is only compared
+    /// at the end of transactions and scripts
+    #[fuseable(ro)]
+    #[serde(default)]
+    check_every: Option<u64>,
+    // expected, writes, checks and mismatches are listed next to the configuration
+    #[fuseable(ro, flatten)]
+    #[serde(skip)]
+    state: Mutex<ChecksumState>,
+}
+
+impl WriteChecksum {
+    fn update(&self, crc: u16, bytes: &[u8]) -> u16 {
+        bytes.iter().fold(crc, |crc, byte| {
+            (0..8).fold(crc ^ (u16::from(*byte) << 8), |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ self.poly
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    pub fn track(&self, address: &[u8], data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+
+        state.writes += 1;
+        state.expected = state.expected.map(|crc| {
+            let crc = if self.include_address { self.update(crc, address) } else { crc };
+            self.update(crc, data)
+        });
+    }
+
+    pub fn synced(&self) -> bool { self.state.lock().unwrap().expected.is_some() }
+
+    pub fn due(&self) -> bool {
+        let state = self.state.lock().unwrap();
+
+        match (self.check_every, state.expected) {
+            (Some(every), Some(_)) => state.writes >= every,
+            _ => false,
+        }
+    }
+
+    pub fn sync(&self, actual: u16) {
+        let mut state = self.state.lock().unwrap();
+
+        state.expected = Some(actual);
+        state.writes = 0;
+    }
+
+    // compares the checksum reported by the device with the expected one, the
+    // checksum is synced to the device afterwards, so every mismatch is only
+    // reported once
+    pub fn compare(&self, actual: u16) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        state.checks += 1;
+
+        let writes = state.writes;
+        let expected = state.expected;
+
+        state.expected = Some(actual);
+        state.writes = 0;
+
+        let ret = match expected {
+            Some(expected) if expected != actual => {
+                state.mismatches += 1;
+
+                Err(format_err!(
+                    "write checksum mismatch, expected 0x{:04X} but the device reported 0x{:04X}, \
+                     at least one of the last {} writes was dropped or corrupted",
+                    expected,
+                    actual,
+                    writes
+                ))
+            }
+            _ => Ok(()),
+        };
+
+        state.last_check = Some(match ret {
+            Ok(()) => "ok".to_owned(),
+            Err(ref e) => e.to_string(),
+        });
+
+        ret
+    }
+
+    // the result of the last compare, this never accesses the device
+    pub fn last_check(&self) -> String {
+        self.state.lock().unwrap().last_check.clone().unwrap_or_else(|| "not checked".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteChecksum;
+    use pretty_assertions::assert_eq;
+
+    fn checksum(include_address: bool) -> WriteChecksum {
+        WriteChecksum {
+            register: "i2c_wrt_checksum".to_string(),
+            poly: 0x1021,
+            include_address,
+            check_every: Some(2),
+            state: Default::default(),
+        }
+    }
+
+    #[test]
+    fn crc_test() {
+        // CRC-16/CCITT-FALSE check value
+        assert_eq!(checksum(false).update(0xFFFF, b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn compare_test() {
+        let c = checksum(true);
+        assert_eq!(c.last_check(), "not checked");
+
+        c.track(&[0x30, 0x12], &[0x00, 0x10]);
+        assert!(!c.due());
+        assert!(c.compare(0x1234).is_ok());
+
+        c.track(&[0x30, 0x12], &[0x00, 0x10]);
+        assert!(!c.due());
+        c.track(&[0x30, 0x12], &[0x00, 0x10]);
+        assert!(c.due());
+
+        let expected = c.update(0x1234, &[0x30, 0x12, 0x00, 0x10, 0x30, 0x12, 0x00, 0x10]);
+        assert!(c.compare(expected).is_ok());
+        assert!(c.compare(expected ^ 1).is_err());
+        assert!(c.last_check().starts_with("write checksum mismatch"));
+        assert!(c.compare(expected ^ 1).is_ok());
+        assert_eq!(c.last_check(), "ok");
+    }
+}
@@ -1,21 +1,52 @@
 use crate::address::{Address, Slice};
 use core::fmt::Debug;
 use derivative::*;
-use failure::format_err;
-use fuseable::{Either, Fuseable, Result};
+use failure::{format_err, Fail};
+use fuseable::{Either, Errno, Fuseable, Result};
 use fuseable_derive::*;
 use i2cdev::{core::I2CDevice, linux::LinuxI2CDevice};
 use memmap::{MmapMut, MmapOptions};
 use paste;
 use serde::*;
 use serde_derive::{Deserialize, Serialize};
-use std::{fs::OpenOptions, sync::RwLock};
+use std::{
+    fs::OpenOptions,
+    sync::{Arc, RwLock},
+};
 
-use crate::bit_slice::{slice, slice_write};
+use crate::{
+    bit_slice::{slice, slice_write},
+    checksum::WriteChecksum,
+    serde_util::bool_false,
+};
+
+mod policy;
+pub use self::policy::{BusRecovery, ChannelPolicy, ChannelTimeout};
 
 pub type CommunicationChannel = Box<dyn CommChannel>;
 
-pub trait CommChannel: Debug + Fuseable {
+// a failed access of the device behind a channel
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct ChannelError(#[cause] failure::Compat<failure::Error>);
+
+impl Errno for ChannelError {
+    fn errno(&self) -> std::os::raw::c_int { libc::EIO }
+}
+
+impl ChannelError {
+    // timeouts keep their own errno
+    fn wrap(error: failure::Error) -> failure::Error {
+        match error.downcast::<ChannelTimeout>() {
+            Ok(timeout) => timeout.into_error(),
+            Err(error) => ChannelError(error.compat()).into_error(),
+        }
+    }
+}
+
+// channels are sent to the thread polling the watches together with their
+// register set
+pub trait CommChannel: Debug + Fuseable + Send {
     // these are assumed to be bytewise
     fn read_value_real(&self, address: &Address) -> Result<Vec<u8>>;
     fn write_value_real(&self, address: &Address, value: Vec<u8>) -> Result<()>;
@@ -33,17 +64,39 @@ pub trait CommChannel: Debug + Fuseable {
     fn mock_mode(&mut self, mock: bool);
     fn get_mock_mode(&self) -> bool;
 
+    fn policy(&self) -> &ChannelPolicy;
+
+    // check that the channel is able to access address, this is called for
+    // every register of a register set at load time
+    fn check_address(&self, _address: &Address) -> Result<()> { Ok(()) }
+
+    // every write that reaches the device is added to checksum from now on
+    fn track_writes(&self, _checksum: Arc<WriteChecksum>) -> Result<()> {
+        Err(format_err!("{:?} does not support write checksums", self))
+    }
+
     fn read_value(&self, address: &Address) -> Result<Vec<u8>> {
         let v = if self.get_mock_mode() {
             self.read_value_mock(&address)
         } else {
-            self.read_value_real(&address)
+            self.policy().run(|| self.read_value_real(address)).map_err(ChannelError::wrap)
         };
 
         v.map(|v| slice(v, address))
     }
 
     fn write_value(&self, address: &Address, value: Vec<u8>) -> Result<()> {
+        self.write_value_verified(address, value, self.policy().verify)
+    }
+
+    // like write_value, but if verify is set the value is read back through the
+    // same slice afterwards and compared to what was written
+    fn write_value_verified(
+        &self,
+        address: &Address,
+        value: Vec<u8>,
+        verify: bool,
+    ) -> Result<()> {
         let new_value = if address.nontrivial_slice() {
             let mut old_value = self.read_value(address)?;
 
@@ -59,32 +112,82 @@ pub trait CommChannel: Debug + Fuseable {
         };
 
         if self.get_mock_mode() {
-            self.write_value_mock(&address, new_value)
+            return self.write_value_mock(address, new_value);
+        }
+
+        let expected = if address.nontrivial_slice() {
+            slice(new_value.clone(), address)
         } else {
-            self.write_value_real(&address, new_value)
+            new_value.clone()
+        };
+
+        self.policy()
+            .run(|| self.write_value_real(address, new_value.clone()))
+            .map_err(ChannelError::wrap)?;
+
+        if verify {
+            let actual = self.read_value(address)?;
+
+            if actual != expected {
+                return Err(format_err!(
+                    "verification of write to {:?} failed, expected {:02X?} but read back {:02X?}",
+                    address,
+                    expected,
+                    actual
+                ));
+            }
         }
+
+        Ok(())
     }
 }
 
 #[derive(Derivative, Serialize, Deserialize, Fuseable)]
-#[derivative(Debug, PartialEq)]
+#[derivative(Debug, Default, PartialEq)]
 struct I2CCdev {
     bus: u8,
     address: u8,
+    // number of bytes used to transmit the register address, if this is not
+    // specified the address is sent as it was parsed
+    #[fuseable(ro)]
+    #[serde(default)]
+    address_width: Option<u8>,
+    // use smbus byte / word data commands instead of plain i2c transfers
+    #[fuseable(ro)]
+    #[serde(default)]
+    smbus: Option<SMBus>,
+    #[fuseable(ro)]
+    #[serde(default)]
+    policy: ChannelPolicy,
     #[fuseable(skip)]
     #[serde(skip)]
     #[derivative(Debug = "ignore", PartialEq = "ignore")]
     dev: RwLock<Option<LinuxI2CDevice>>,
+    #[fuseable(skip)]
+    #[serde(skip)]
+    #[derivative(PartialEq = "ignore")]
+    write_checksum: RwLock<Option<Arc<WriteChecksum>>>,
     #[fuseable(ro)]
     #[serde(skip)]
     mock: bool,
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize, Fuseable)]
+struct SMBus {
+    // packet error checking
+    #[fuseable(ro)]
+    #[serde(default = "bool_false")]
+    pec: bool,
+}
+
 #[derive(Derivative, Serialize, Deserialize, Fuseable)]
-#[derivative(Debug, PartialEq)]
+#[derivative(Debug, Default, PartialEq)]
 struct MMAPGPIO {
     base: u64,
     len: u64,
+    #[fuseable(ro)]
+    #[serde(default)]
+    policy: ChannelPolicy,
     #[fuseable(skip)]
     #[serde(skip)]
     #[derivative(Debug = "ignore", PartialEq = "ignore")]
@@ -96,8 +199,47 @@ struct MMAPGPIO {
 
 impl I2CCdev {
     fn init(&self) -> Result<LinuxI2CDevice> {
-        LinuxI2CDevice::new(format!("/dev/i2c-{}", self.bus), u16::from(self.address))
-            .map_err(|e| e.into())
+        let mut dev =
+            LinuxI2CDevice::new(format!("/dev/i2c-{}", self.bus), u16::from(self.address))?;
+
+        if let Some(SMBus { pec }) = self.smbus {
+            dev.set_smbus_pec(pec)?;
+        }
+
+        Ok(dev)
+    }
+
+    fn register_address(&self, address: &Address) -> Result<Vec<u8>> {
+        pad_register_address(&address.base, self.address_width)
+    }
+}
+
+// pads (or strips leading zeros of) the big endian register address base to
+// exactly width bytes
+fn pad_register_address(base: &[u8], width: Option<u8>) -> Result<Vec<u8>> {
+    match width {
+        None => Ok(base.to_vec()),
+        Some(width @ 1) | Some(width @ 2) | Some(width @ 4) => {
+            let width = width as usize;
+            let significant_bytes = base.iter().skip_while(|b| **b == 0).count();
+
+            if significant_bytes > width {
+                return Err(format_err!(
+                    "register address {:?} does not fit into {} address bytes",
+                    base,
+                    width
+                ));
+            }
+
+            let mut padded = vec![0; width.saturating_sub(base.len())];
+            padded.extend(&base[base.len().saturating_sub(width)..]);
+
+            Ok(padded)
+        }
+        Some(width) => Err(format_err!(
+            "unsupported i2c address width {}, only 1, 2 or 4 bytes are supported",
+            width
+        )),
     }
 }
 
@@ -117,34 +259,105 @@ impl MMAPGPIO {
 
 impl CommChannel for I2CCdev {
     fn read_value_real(&self, address: &Address) -> Result<Vec<u8>> {
+        let register = self.register_address(address)?;
+        let bytes = address
+            .bytes()
+            .ok_or_else(|| format_err!("I2CCdev doesn't support unbounded read"))?;
+
         with_dev(
             &self.dev,
-            |i2c_dev| {
-                i2c_dev.write(&address.base)?;
-                let mut ret = vec![
-                    0;
-                    address.bytes().ok_or_else(|| format_err!(
-                        "I2CCdev doesn't support unbounded read"
-                    ))?
-                ];
-                i2c_dev.read(&mut ret)?;
-                Ok(ret)
+            |i2c_dev| match self.smbus {
+                Some(_) => match bytes {
+                    1 => Ok(vec![i2c_dev.smbus_read_byte_data(register[0])?]),
+                    2 => {
+                        let word = i2c_dev.smbus_read_word_data(register[0])?;
+                        Ok(vec![(word >> 8) as u8, word as u8])
+                    }
+                    _ => Err(format_err!("smbus can only read one or two bytes, not {}", bytes)),
+                },
+                None => {
+                    i2c_dev.write(&register)?;
+                    let mut ret = vec![0; bytes];
+                    i2c_dev.read(&mut ret)?;
+                    Ok(ret)
+                }
             },
             || self.init(),
         )
     }
 
     fn write_value_real(&self, address: &Address, value: Vec<u8>) -> Result<()> {
-        let mut tmp = Vec::new();
-        tmp.extend(&address.base);
-        tmp.extend(value);
+        let register = self.register_address(address)?;
 
-        with_dev(&self.dev, |i2c_dev| i2c_dev.write(&tmp).map_err(|e| e.into()), || self.init())
+        let ret = with_dev(
+            &self.dev,
+            |i2c_dev| match self.smbus {
+                Some(_) => match value[..] {
+                    [byte] => i2c_dev.smbus_write_byte_data(register[0], byte).map_err(|e| e.into()),
+                    [high, low] => i2c_dev
+                        .smbus_write_word_data(register[0], (u16::from(high) << 8) | u16::from(low))
+                        .map_err(|e| e.into()),
+                    _ => Err(format_err!(
+                        "smbus can only write one or two bytes, not {}",
+                        value.len()
+                    )),
+                },
+                None => {
+                    let mut tmp = register.clone();
+                    tmp.extend(&value);
+
+                    i2c_dev.write(&tmp).map_err(|e| e.into())
+                }
+            },
+            || self.init(),
+        );
+
+        if ret.is_ok() {
+            if let Some(ref checksum) = *self.write_checksum.read().unwrap() {
+                checksum.track(&register, &value);
+            }
+        }
+
+        ret
     }
 
     fn mock_mode(&mut self, mock: bool) { self.mock = mock; }
 
     fn get_mock_mode(&self) -> bool { self.mock }
+
+    fn policy(&self) -> &ChannelPolicy { &self.policy }
+
+    fn track_writes(&self, checksum: Arc<WriteChecksum>) -> Result<()> {
+        *self.write_checksum.write().unwrap() = Some(checksum);
+        Ok(())
+    }
+
+    fn check_address(&self, address: &Address) -> Result<()> {
+        let register = self.register_address(address)?;
+
+        if self.smbus.is_some() {
+            if register.len() != 1 {
+                return Err(format_err!(
+                    "smbus register addresses are one byte long, got {:?}",
+                    register
+                ));
+            }
+
+            match address.bytes() {
+                Some(1) | Some(2) => {}
+                bytes => {
+                    return Err(format_err!(
+                        "smbus only supports byte and word data, but register at {:?} is {:?} \
+                         bytes wide",
+                        register,
+                        bytes
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl CommChannel for MMAPGPIO {
@@ -189,6 +402,8 @@ impl CommChannel for MMAPGPIO {
     fn mock_mode(&mut self, mock: bool) { self.mock = mock; }
 
     fn get_mock_mode(&self) -> bool { self.mock }
+
+    fn policy(&self) -> &ChannelPolicy { &self.policy }
 }
 
 fn with_dev<D, F, I, T>(dev: &RwLock<Option<D>>, func: F, init: I) -> Result<T>
@@ -217,11 +432,15 @@ where
 macro_rules! comm_channel_config {
     ( $($struct:ident => $tag:tt),* ) => {
         paste::item!{
+            // the config is kept as the channel, so the mode can be switched by writing
+            // to channel/variant. the device of the new mode starts with a default
+            // configuration, that has to be completed by writing its fields
             #[derive(Debug, PartialEq, Serialize, Deserialize, Fuseable)]
             #[serde(tag = "mode")]
             enum CommChannelConfig {
                 $(
                     #[serde(rename = $tag)]
+                    #[fuseable(rename = $tag)]
                     [<$struct Channel___>] {
                         #[serde(flatten)]
                         channel: $struct,
@@ -230,10 +449,18 @@ macro_rules! comm_channel_config {
             }
 
             impl CommChannelConfig {
-                fn convert_to_comm_channel(self) -> Box<dyn CommChannel> {
+                fn channel(&self) -> &dyn CommChannel {
                     match self {
                         $(
-                            CommChannelConfig::[<$struct Channel___>] { channel } => { Box::new(channel) },
+                            CommChannelConfig::[<$struct Channel___>] { channel } => channel,
+                        )*
+                    }
+                }
+
+                fn channel_mut(&mut self) -> &mut dyn CommChannel {
+                    match self {
+                        $(
+                            CommChannelConfig::[<$struct Channel___>] { channel } => channel,
                         )*
                     }
                 }
@@ -244,12 +471,56 @@ macro_rules! comm_channel_config {
 
 comm_channel_config!(I2CCdev => "i2c-cdev", MMAPGPIO => "mmaped-gpio");
 
+impl CommChannel for CommChannelConfig {
+    fn read_value_real(&self, address: &Address) -> Result<Vec<u8>> {
+        self.channel().read_value_real(address)
+    }
+
+    fn write_value_real(&self, address: &Address, value: Vec<u8>) -> Result<()> {
+        self.channel().write_value_real(address, value)
+    }
+
+    fn mock_mode(&mut self, mock: bool) { self.channel_mut().mock_mode(mock) }
+
+    fn get_mock_mode(&self) -> bool { self.channel().get_mock_mode() }
+
+    fn policy(&self) -> &ChannelPolicy { self.channel().policy() }
+
+    fn check_address(&self, address: &Address) -> Result<()> {
+        self.channel().check_address(address)
+    }
+
+    fn track_writes(&self, checksum: Arc<WriteChecksum>) -> Result<()> {
+        self.channel().track_writes(checksum)
+    }
+}
+
 impl<'de> Deserialize<'de> for Box<dyn CommChannel> {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let config = CommChannelConfig::deserialize(deserializer)?;
-        Ok(config.convert_to_comm_channel())
+        Ok(Box::new(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pad_register_address;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn register_address_width_test() {
+        assert_eq!(pad_register_address(&[0x30, 0x12], None).ok(), Some(vec![0x30, 0x12]));
+        assert_eq!(pad_register_address(&[0x12], Some(2)).ok(), Some(vec![0x00, 0x12]));
+        assert_eq!(pad_register_address(&[0x30, 0x12], Some(2)).ok(), Some(vec![0x30, 0x12]));
+        assert_eq!(
+            pad_register_address(&[0x30, 0x12], Some(4)).ok(),
+            Some(vec![0x00, 0x00, 0x30, 0x12])
+        );
+        assert_eq!(pad_register_address(&[0x00, 0x12], Some(1)).ok(), Some(vec![0x12]));
+        assert!(pad_register_address(&[0x30, 0x12], Some(1)).is_err());
+        assert!(pad_register_address(&[0x12], Some(3)).is_err());
     }
 }
@@ -0,0 +1,233 @@
+use ::log::warn;
+use derivative::*;
+use failure::Fail;
+use fuseable::{Either, Errno, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    sync::{Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub type RecoveryHook = Box<dyn Fn() -> Result<()> + Send>;
+
+#[derive(Debug, Default, PartialEq, Serialize, Fuseable)]
+pub struct ChannelStats {
+    #[fuseable(ro)]
+    retries: u64,
+    #[fuseable(ro)]
+    failures: u64,
+    #[fuseable(ro)]
+    recoveries: u64,
+    // transactions that failed, because they took longer than the timeout
+    #[fuseable(ro)]
+    timeouts: u64,
+}
+
+// a transaction that took longer than the timeout of its channel
+#[derive(Debug, Fail)]
+#[fail(
+    display = "the transaction took {:?}, which is longer than the timeout of {:?}",
+    elapsed, timeout
+)]
+pub struct ChannelTimeout {
+    elapsed: Duration,
+    timeout: Duration,
+}
+
+impl Errno for ChannelTimeout {
+    fn errno(&self) -> std::os::raw::c_int { libc::ETIMEDOUT }
+}
+
+// a register of another register set, that is used to get the bus of a channel
+// back into a working state (for example by toggling the reset line of a
+// sensor)
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Fuseable)]
+pub struct BusRecovery {
+    #[fuseable(ro)]
+    pub register_set: String,
+    #[fuseable(ro)]
+    pub register: String,
+    /// values written to the register one after another
+    #[fuseable(ro)]
+    pub sequence: Vec<u64>,
+    /// delay after each write of the sequence
+    #[fuseable(ro)]
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+#[derive(Derivative, Serialize, Deserialize, Fuseable)]
+#[derivative(Debug, Default, PartialEq)]
+pub struct ChannelPolicy {
+    #[fuseable(ro)]
+    #[serde(default)]
+    max_retries: u32,
+    /// delay before the first retry, doubled for every further retry
+    #[fuseable(ro)]
+    #[serde(default)]
+    backoff_ms: u64,
+    /// a transaction (including its retries) taking longer than this fails,
+    /// an access that blocks can only be failed once it returns
+    #[fuseable(ro)]
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// used once all retries failed, followed by one last attempt
+    #[fuseable(ro)]
+    #[serde(default)]
+    pub recovery: Option<BusRecovery>,
+    /// read back every write, can be overridden by register sets and registers
+    #[fuseable(ro)]
+    #[serde(default)]
+    pub verify: bool,
+    #[fuseable(ro)]
+    #[serde(skip)]
+    #[derivative(PartialEq = "ignore")]
+    stats: Mutex<ChannelStats>,
+    #[fuseable(skip)]
+    #[serde(skip)]
+    #[derivative(Debug = "ignore", PartialEq = "ignore")]
+    recovery_hook: RwLock<Option<RecoveryHook>>,
+}
+
+impl ChannelPolicy {
+    pub fn set_recovery_hook(&self, hook: RecoveryHook) {
+        *self.recovery_hook.write().unwrap() = Some(hook);
+    }
+
+    pub fn run<T, F>(&self, mut transaction: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let start = Instant::now();
+        let mut attempt = 0;
+
+        loop {
+            let err = match self.attempt(start, &mut transaction) {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+
+            // retrying is pointless once the timeout passed
+            if err.downcast_ref::<ChannelTimeout>().is_some() {
+                self.stats.lock().unwrap().failures += 1;
+                return Err(err);
+            }
+
+            if attempt >= self.max_retries {
+                return self.recover(start, &mut transaction, err);
+            }
+
+            attempt += 1;
+            self.stats.lock().unwrap().retries += 1;
+
+            warn!("transaction failed ({}), retry {} of {}", err, attempt, self.max_retries);
+
+            thread::sleep(Duration::from_millis(self.backoff_ms << (attempt - 1).min(16)));
+        }
+    }
+
+    // fails with a ChannelTimeout once the transaction started at start took
+    // longer than the timeout
+    fn check_timeout(&self, start: Instant) -> Result<()> {
+        match self.timeout_ms.map(Duration::from_millis) {
+            Some(timeout) if start.elapsed() > timeout => {
+                self.stats.lock().unwrap().timeouts += 1;
+
+                Err(ChannelTimeout { elapsed: start.elapsed(), timeout }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // an attempt is not started once the timeout passed. one that blocks can not
+    // be interrupted, so it fails once it returns after the timeout, even if it
+    // succeeded
+    fn attempt<T, F>(&self, start: Instant, transaction: &mut F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        self.check_timeout(start)?;
+        let result = transaction();
+        self.check_timeout(start)?;
+
+        result
+    }
+
+    fn recover<T, F>(&self, start: Instant, transaction: &mut F, err: failure::Error) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let result = match *self.recovery_hook.read().unwrap() {
+            Some(ref hook) => {
+                warn!("transaction failed ({}), trying to recover the bus", err);
+
+                self.stats.lock().unwrap().recoveries += 1;
+
+                hook().and_then(|_| self.attempt(start, transaction))
+            }
+            None => Err(err),
+        };
+
+        if result.is_err() {
+            self.stats.lock().unwrap().failures += 1;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelPolicy, ChannelTimeout};
+    use failure::format_err;
+    use pretty_assertions::assert_eq;
+    use std::{cell::Cell, thread, time::Duration};
+
+    #[test]
+    fn timeout_test() {
+        let policy = ChannelPolicy { max_retries: 3, timeout_ms: Some(100), ..Default::default() };
+        let attempts = Cell::new(0);
+
+        // a slow transaction fails, even though it succeeded
+        let result = policy.run(|| {
+            attempts.set(attempts.get() + 1);
+            thread::sleep(Duration::from_millis(150));
+            Ok(())
+        });
+
+        assert!(result.unwrap_err().downcast_ref::<ChannelTimeout>().is_some());
+        assert_eq!(attempts.get(), 1);
+
+        // failed attempts are only retried until the timeout passed
+        attempts.set(0);
+
+        let result: fuseable::Result<()> = policy.run(|| {
+            attempts.set(attempts.get() + 1);
+            thread::sleep(Duration::from_millis(60));
+            Err(format_err!("nack"))
+        });
+
+        assert!(result.unwrap_err().downcast_ref::<ChannelTimeout>().is_some());
+        assert_eq!(attempts.get(), 2);
+
+        // fast transactions are retried as usual
+        attempts.set(0);
+
+        let result = policy.run(|| {
+            attempts.set(attempts.get() + 1);
+
+            if attempts.get() < 3 {
+                Err(format_err!("nack"))
+            } else {
+                Ok(attempts.get())
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+
+        let stats = policy.stats.lock().unwrap();
+        assert_eq!((stats.retries, stats.failures, stats.timeouts), (3, 2, 2));
+    }
+}
@@ -1,7 +1,10 @@
 #![feature(inner_deref)]
 mod address;
 mod bit_slice;
+mod checksum;
 mod communication_channel;
 pub mod sensor;
 pub mod serde_util;
+mod shadow;
 mod valuemap;
+mod watch;
@@ -1,7 +1,7 @@
 use ctrl::{sensor::Camera, serde_util::FILE_OPENER};
 use env_logger;
 use fuseable::FuseableWrapper;
-use std::{ffi::OsStr, io::Read, path::PathBuf};
+use std::{ffi::OsStr, io::Read, path::PathBuf, thread, time::Duration};
 use structopt::StructOpt;
 
 /// Basic daemon for controlling the various components of a camera
@@ -18,6 +18,15 @@ struct Opt {
     /// Mountpoint of the fuse config filesystem
     #[structopt(short = "d", long = "mountpoint", default_value = ".propfs")]
     mountpoint: String,
+    /// Register or function to watch for changes, relative to registers (for
+    /// example sensor/map/frame_count), its mtime is updated when it changes
+    #[structopt(short = "w", long = "watch")]
+    watch: Vec<String>,
+    /// Time in milliseconds after which filesystem requests fail with
+    /// ETIMEDOUT, checked before accessing the hardware and between the steps
+    /// of scripts
+    #[structopt(short = "t", long = "timeout")]
+    timeout: Option<u64>,
 }
 
 fn main() {
@@ -34,6 +43,14 @@ fn main() {
     let mut sensor: Camera = serde_yaml::from_str(&contents).unwrap();
     sensor.mocked(opt.mock);
 
+    for path in &opt.watch {
+        sensor.watch(path).unwrap();
+    }
+
+    // the watches are only polled by this thread, stat'ing a file never polls
+    let poller = sensor.poller();
+    thread::spawn(move || poller.run());
+
 
     // println!("{:#?}", sensor);
 
@@ -43,7 +60,12 @@ fn main() {
     let sensor: Box<Fuseable> = Box::new(cached_sensor);
     */
 
-    let s = FuseableWrapper::new(sensor);
+    let mut s = FuseableWrapper::new(sensor);
+
+    if let Some(timeout) = opt.timeout {
+        s = s.with_timeout(Duration::from_millis(timeout));
+    }
+
     // let s = CachedFuseable::new(s, 65536);
     // let s: Box<Fuseable> = Box::new(s);
     let fuse_args: Vec<&OsStr> = vec![&OsStr::new("-o"), &OsStr::new("auto_unmount")];
@@ -1,11 +1,16 @@
 use crate::{
-    address::Address,
-    communication_channel::CommunicationChannel,
-    serde_util::{bool_false, by_path},
+    address::{Address, Slice},
+    bit_slice::{slice, slice_write},
+    checksum::WriteChecksum,
+    communication_channel::{BusRecovery, CommunicationChannel},
+    serde_util::{bool_false, by_path, group_id, user_id},
+    shadow::Shadow,
     valuemap::*,
+    watch::{Callback, Watches},
 };
+use ::log::warn;
 use failure::format_err;
-use fuseable::{type_name, Either, FuseableError, Fuseable};
+use fuseable::{type_name, Either, Fuseable, FuseableContext, FuseableError, Permissions, Request};
 use fuseable_derive::Fuseable;
 use itertools::{izip, Itertools};
 use num::Num;
@@ -15,9 +20,36 @@ use serde_derive::*;
 use std::{
     collections::HashMap,
     iter::FromIterator,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
     fmt::Debug,
-    ops::Deref
+    ops::Deref,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+mod output;
+mod readout;
+mod roi;
+mod shading;
+mod status;
+mod test_pattern;
+#[cfg(test)]
+mod testing;
+mod timing;
+
+use self::{
+    output::OutputInterface,
+    readout::Readout,
+    roi::Roi,
+    shading::Shading,
+    status::{FrameSampler, Status},
+    test_pattern::TestPattern,
+    timing::{Exposure, FrameRate},
 };
 
 #[derive(Debug, Serialize, Deserialize, Fuseable, Clone)]
@@ -26,6 +58,35 @@ enum Range {
     MinMax { min: i64, max: i64 },
 }
 
+// the rw key of the register maps, registers without one are read write
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum Access {
+    #[serde(rename = "RO")]
+    ReadOnly,
+    #[serde(rename = "WO")]
+    WriteOnly,
+    #[serde(rename = "RW")]
+    ReadWrite,
+}
+
+impl Default for Access {
+    fn default() -> Access { Access::ReadWrite }
+}
+
+impl Access {
+    fn readable(self) -> bool { self != Access::WriteOnly }
+
+    fn writable(self) -> bool { self != Access::ReadOnly }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Access::ReadOnly => "ro",
+            Access::WriteOnly => "wo",
+            Access::ReadWrite => "rw",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Fuseable, Clone)]
 #[serde(untagged)]
 enum Description {
@@ -33,9 +94,15 @@ enum Description {
     LongAndShort { long: String, short: String },
 }
 
-// #[fuseable(virtual_field(name = "value", read = "self.read_value", write =
-// "self.write_value", is_dir = "self.value_is_dir"))]
+// the value of a register is read and written through the register set it belongs to
 #[derive(Debug, Serialize, Fuseable, Clone)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "RegisterSetting"
+))]
 pub struct Register {
     #[fuseable(ro)]
     pub address: Address,
@@ -51,6 +118,18 @@ pub struct Register {
     default: Option<u64>,
     #[fuseable(ro)]
     description: Option<Description>,
+    /// read back the value after writing it, overrides the setting of the register set
+    #[fuseable(ro)]
+    verify: Option<bool>,
+    /// volatile registers are never served from the shadow of the register set
+    #[fuseable(ro)]
+    volatile: bool,
+    #[fuseable(ro)]
+    unit: Option<String>,
+    // reported as the access xattr of the value
+    #[fuseable(skip)]
+    #[serde(rename = "rw")]
+    access: Access,
 }
 
 impl<'de> Deserialize<'de> for Register {
@@ -68,6 +147,12 @@ impl<'de> Deserialize<'de> for Register {
             #[serde(default, deserialize_with = "by_string_option_num")]
             default: Option<u64>,
             description: Option<Description>,
+            verify: Option<bool>,
+            #[serde(default = "bool_false")]
+            volatile: bool,
+            unit: Option<String>,
+            #[serde(default)]
+            rw: Access,
         }
 
         let reg = RegisterStringAddr::deserialize(deserializer)?;
@@ -82,6 +167,10 @@ impl<'de> Deserialize<'de> for Register {
             range: reg.range,
             default: reg.default,
             description: reg.description,
+            verify: reg.verify,
+            volatile: reg.volatile,
+            unit: reg.unit,
+            access: reg.rw,
         })
     }
 }
@@ -143,6 +232,46 @@ where
     }
 }
 
+// the metadata shared by registers and functions, exposed as extended
+// attributes of their value
+fn xattrs(
+    address: &Address,
+    description: &Option<Description>,
+    default: Option<u64>,
+    unit: &Option<String>,
+    access: &str,
+) -> Vec<(String, String)> {
+    let mut xattrs = vec![
+        ("address".to_owned(), address.to_string()),
+        ("access".to_owned(), access.to_owned()),
+    ];
+
+    if let Some(bytes) = address.bytes() {
+        xattrs.push(("width".to_owned(), bytes.to_string()));
+    }
+
+    if let Some(default) = default {
+        xattrs.push(("default".to_owned(), format!("{:#x}", default)));
+    }
+
+    match description {
+        Some(Description::Simple(description)) => {
+            xattrs.push(("description".to_owned(), description.clone()));
+        }
+        Some(Description::LongAndShort { long, short }) => {
+            xattrs.push(("description.short".to_owned(), short.clone()));
+            xattrs.push(("description.long".to_owned(), long.clone()));
+        }
+        None => {}
+    }
+
+    if let Some(unit) = unit {
+        xattrs.push(("unit".to_owned(), unit.clone()));
+    }
+
+    xattrs
+}
+
 fn to_hex(v: Vec<u8>) -> String {
     if !v.is_empty() {
         "0x".to_string() + &v.iter().map(|v| format!("{:02X}", v).to_string()).collect::<String>()
@@ -152,91 +281,646 @@ fn to_hex(v: Vec<u8>) -> String {
 }
 
 impl Register {
+    fn xattrs(&self) -> Vec<(String, String)> {
+        let mut xattrs =
+            xattrs(&self.address, &self.description, self.default, &self.unit, self.access.as_str());
+
+        if let Some(ref mask) = self.mask {
+            xattrs.push(("mask".to_owned(), mask.clone()));
+        }
+
+        if let Some(Range::MinMax { min, max }) = self.range {
+            xattrs.push(("range".to_owned(), format!("{}..{}", min, max)));
+        }
+
+        xattrs
+    }
+
     fn read_value(
         &self,
         path: &mut dyn Iterator<Item = &str>,
-        comm_channel: &CommunicationChannel
+        register_set: &RegisterSetting,
+        _request: &Request,
     ) -> fuseable::Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
             None => {
-                comm_channel.read_value(&self.address).map(|v| Either::Right(to_hex(v)))
+                register_set.read_register_value(self).map(|v| Either::Right(to_hex(v)))
             }
         }
     }
 
+    // parses a value written to this register, returning the (optional) mask and
+    // the value, both padded to the width of the register
+    fn parse_value(&self, value: &[u8]) -> fuseable::Result<(Option<Vec<u8>>, Vec<u8>)> {
+        if let Some(width) = self.width {
+            let (mask, mut value) = parse_num_mask(String::from_utf8_lossy(value))
+                .map_err(|e| FuseableError::invalid_value(e.to_string()))?;
+
+            if value.len() > width as usize {
+                return Err(FuseableError::invalid_value(format!(
+                    "value {:?} to write was longer ({}) than register {:?} with width of {}",
+                    value,
+                    value.len(),
+                    self,
+                    width
+                )));
+            }
+
+            while value.len() < width as usize {
+                // TODO(robin): which way around?, really efficient this way around
+                // (vs value.push(0))
+                value.insert(0, 0);
+            }
+
+            let mask = mask.map(|mut mask| {
+                while mask.len() < width as usize {
+                    // TODO(robin): which way around?, really efficient this way around
+                    mask.insert(0, 0);
+                }
+
+                mask
+            });
+
+            Ok((mask, value))
+        } else {
+            Err(format_err!(
+                "the register written to {:?} did not specify a width, don't know what to do",
+                self
+            ))
+        }
+    }
+
     fn write_value(
         &self,
         path: &mut dyn Iterator<Item = &str>,
         value: Vec<u8>,
-        comm_channel: &CommunicationChannel
+        register_set: &RegisterSetting,
+        _request: &Request,
     ) -> fuseable::Result<()> {
         match path.next() {
             Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
             None => {
                 println!("writing");
 
-                if let Some(width) = self.width {
-                    let (mask, mut value) = parse_num_mask(String::from_utf8_lossy(&value))?;
-
-                    if value.len() > width as usize {
-                        return Err(format_err!("value {:?} to write was longer ({}) than register {:?} with width of {}", value, value.len(), self, width));
+                let (mask, value) = self.parse_value(&value)?;
+
+                let value = match mask {
+                    Some(mask) => {
+                        // TODO(robin): this currently interprets a too short value, as if the
+                        // missing part should not be assigned and the old value (that is
+                        // already in the register) be kept
+                        // it is unclear if this is the wanted / intuitive behaviour, or if the
+                        // opposite is the case (note this applies only if a mask is specified,
+                        // maybe we only want to allow masks, when their width matches the
+                        // expected width
+
+                        // TODO(robin): this also needs to account for little endian vs big
+                        // endian for value 0x12345678 at 0x0,
+                        // little endian has 0x78 is stored at 0x0, 0x56 is stored at 0x1 and so
+                        // on big endian has 0x12 stored at 0x0,
+                        // 0x34 stored at 0x1 and so on
+                        // need to define internal byte order =>
+                        // little endian -- not so intuitive
+                        // big endian -- would be more efficient and more intuitive
+                        let current_value = register_set.read_register_value(self)?;
+
+                        izip!(mask, value, current_value)
+                            .map(|(m, val, cur)| (val & m) | (cur & !m))
+                            .collect()
                     }
+                    None => value,
+                };
 
-                    while value.len() < width as usize {
-                        value.insert(0, 0); // TODO(robin): which way around?, really efficient this way around (vs value.push(0))
-                    }
+                register_set.write_address(&self.address, value.clone(), self.verify)?;
 
-                    let value = match mask {
-                        Some(mut mask) => {
-                            // TODO(robin): this currently interprets a too short value, as if the
-                            // missing part should not be assigned and the old value (that is
-                            // already in the register) be kept
-                            // it is unclear if this is the wanted / intuitive behaviour, or if the
-                            // opposite is the case (note this applies only if a mask is specified,
-                            // maybe we only want to allow masks, when their width matches the
-                            // expected width
-
-                            // TODO(robin): this also needs to account for little endian vs big
-                            // endian for value 0x12345678 at 0x0,
-                            // little endian has 0x78 is stored at 0x0, 0x56 is stored at 0x1 and so
-                            // on big endian has 0x12 stored at 0x0,
-                            // 0x34 stored at 0x1 and so on
-                            // need to define internal byte order =>
-                            // little endian -- not so intuitive
-                            // big endian -- would be more efficient and more intuitive
-                            while mask.len() < width as usize {
-                                mask.insert(0, 0); // TODO(robin): which way around?, really efficient this way around
-                            }
-
-                            let current_value = comm_channel.read_value(&self.address)?;
-
-                            izip!(mask, value, current_value)
-                                .map(|(m, val, cur)| (val & m) | (cur & !m))
-                                .collect()
-                        }
-                        None => value,
-                    };
-
-                    comm_channel.write_value(&self.address, value)
-                } else {
-                    Err(format_err!("the register written to {:?} did not specify a width, don't know what to do", self))
+                if !self.volatile {
+                    register_set.shadow.set(self.address.base.clone(), value);
                 }
+
+                Ok(())
             }
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StagedKind {
+    Register,
+    Function,
+}
+
+// a write to the value of a register or function, that is applied once the
+// transaction it belongs to is committed
+#[derive(Debug)]
+struct StagedWrite {
+    kind: StagedKind,
+    name: String,
+    value: Vec<u8>,
+}
+
+impl ToString for StagedWrite {
+    fn to_string(&self) -> String {
+        let kind = match self.kind {
+            StagedKind::Register => "map",
+            StagedKind::Function => "functions",
+        };
+
+        format!("{}/{} = {}", kind, self.name, String::from_utf8_lossy(&self.value).trim())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Context {
+    // appended to the name of a parameter to get the function of this context
+    suffix: String,
+    // value of the select function that makes this context the active one
+    value: String,
+}
+
+// parallel sets of functions (for example red_gain and red_gain_cb), that can
+// be addressed as context/<context>/<parameter>
+#[derive(Debug, Deserialize)]
+struct Contexts {
+    // function selecting the active context
+    select: String,
+    #[serde(flatten)]
+    contexts: HashMap<String, Context>,
+    // the parameters available in every context, filled in once the functions
+    // are known
+    #[serde(skip)]
+    parameters: Vec<String>,
+}
+
+impl Contexts {
+    fn find_parameters(&mut self, functions: &HashMap<String, Function>) {
+        let parameters: std::collections::BTreeSet<&str> = functions
+            .keys()
+            .flat_map(|name| {
+                self.contexts.values().filter_map(move |c| {
+                    if name.ends_with(&c.suffix) { Some(&name[..name.len() - c.suffix.len()]) } else { None }
+                })
+            })
+            .filter(|parameter| {
+                self.contexts.values().all(|c| functions.contains_key(&(parameter.to_string() + &c.suffix)))
+            })
+            .collect();
+
+        self.parameters = parameters.into_iter().map(str::to_owned).collect();
+    }
+
+    fn context(&self, context: &str) -> fuseable::Result<&Context> {
+        self.contexts.get(context).ok_or_else(|| FuseableError::not_found(context))
+    }
+
+    // the name of the function implementing parameter in context
+    fn function(&self, context: &str, parameter: &str) -> fuseable::Result<String> {
+        let context = self.context(context)?;
+
+        if self.parameters.iter().any(|p| p == parameter) {
+            Ok(parameter.to_owned() + &context.suffix)
+        } else {
+            Err(FuseableError::not_found(parameter))
+        }
+    }
+}
+
+fn unwatchable(path: &str) -> failure::Error {
+    FuseableError::invalid_value(format!("can only watch map/<register> or functions/<function>, got {}", path))
+}
+
 #[derive(Debug)]
 pub struct RegisterSetting {
     channel: CommunicationChannel,
     map: HashMap<String, Register>,
     functions: HashMap<String, Function>,
+    verify: Option<bool>,
+    shadow: Shadow,
+    // register that is set while a transaction is committed, so all staged writes
+    // take effect at the same time (for example grouped_parameter_hold)
+    hold: Option<String>,
+    // how many with_hold calls are running, only the outermost one sets and
+    // releases the hold register
+    holding: AtomicUsize,
+    transaction: Option<Vec<StagedWrite>>,
+    checksum: Option<Arc<WriteChecksum>>,
+    contexts: Option<Contexts>,
+    watches: Watches,
+    // only they (and root) may write to the register set, if neither is given
+    // everyone may
+    owner: Option<u32>,
+    group: Option<u32>,
 }
 
 impl RegisterSetting {
+    // writes value using the channel of this register set, reading it back if
+    // verification is enabled for the register, the register set or the channel
+    // (in this order of precedence)
+    fn write_address(&self, address: &Address, value: Vec<u8>, verify: Option<bool>) -> fuseable::Result<()> {
+        let verify = verify.or(self.verify).unwrap_or_else(|| self.channel.policy().verify);
+
+        if let Some(ref checksum) = self.checksum {
+            if !checksum.synced() {
+                self.sync_checksum()?;
+            }
+        }
+
+        let ret = self.channel.write_value_verified(address, value, verify);
+
+        self.watches.forget_reads();
+
+        if ret.is_err() {
+            self.shadow.invalidate(&address.base);
+        }
+
+        match self.checksum {
+            Some(ref checksum) if ret.is_ok() && checksum.due() => self.check_checksum(),
+            _ => ret,
+        }
+    }
+
+    fn read_checksum(&self, checksum: &WriteChecksum) -> fuseable::Result<u16> {
+        let value = self.channel.read_value(&self.map[&checksum.register].address)?;
+
+        Ok(value.iter().fold(0, |acc, b| (acc << 8) | u16::from(*b)))
+    }
+
+    // takes the current checksum of the device as the starting point for all
+    // following writes, needed after the device was reset
+    pub fn sync_checksum(&self) -> fuseable::Result<()> {
+        match self.checksum {
+            Some(ref checksum) => {
+                checksum.sync(self.read_checksum(checksum)?);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // compares the checksum of all writes since the last check with the one of
+    // the device
+    pub fn check_checksum(&self) -> fuseable::Result<()> {
+        match self.checksum {
+            Some(ref checksum) => checksum.compare(self.read_checksum(checksum)?),
+            None => Ok(()),
+        }
+    }
+
+    // reads the whole register, if it is not volatile the last known value from
+    // the shadow is used if available
+    fn read_register_value(&self, register: &Register) -> fuseable::Result<Vec<u8>> {
+        if register.volatile {
+            return self.channel.read_value(&register.address);
+        }
+
+        match self.shadow.get(&register.address.base) {
+            Some(value) => Ok(value),
+            None => {
+                let value = self.channel.read_value(&register.address)?;
+                self.shadow.set(register.address.base.clone(), value.clone());
+
+                Ok(value)
+            }
+        }
+    }
+
+    // reads the slice of a function, going through the shadow of its register
+    // if it has one
+    fn read_function_value(&self, function: &Function) -> fuseable::Result<Vec<u8>> {
+        match function.register.as_ref().and_then(|name| self.map.get(name)) {
+            Some(register) => self.read_register_value(register).map(|v| slice(v, &function.addr)),
+            None => self.channel.read_value(&function.addr),
+        }
+    }
+
+    // writes the already encoded value of a function. functions of a register are
+    // merged into the value of the register (from the shadow if it is cached) and
+    // the whole register is written, so the shadow stays up to date and no
+    // additional read is needed
+    fn write_function_value(&self, function: &Function, value: Vec<u8>) -> fuseable::Result<()> {
+        let register = function.register.as_ref().and_then(|name| self.map.get(name));
+
+        match (register, function.addr.slice.as_ref()) {
+            (Some(register), Some(_)) => {
+                let mut register_value = self.read_register_value(register)?;
+
+                if function.addr.bytes().unwrap_or(0) > register_value.len() {
+                    return Err(format_err!("function {:?} does not fit into register {:?}", function.addr, register.address));
+                }
+
+                slice_write(&mut register_value, value, &function.addr);
+
+                self.write_address(&register.address, register_value.clone(), function.verify)?;
+
+                if !register.volatile {
+                    self.shadow.set(register.address.base.clone(), register_value);
+                }
+
+                Ok(())
+            }
+            _ => {
+                self.write_address(&function.addr, value, function.verify)?;
+
+                // the channel took care of merging the slice, so a shadowed value
+                // of the register is outdated now
+                self.shadow.invalidate(&function.addr.base);
+
+                Ok(())
+            }
+        }
+    }
+
+    // writing the name of a register only invalidates / refreshes this register,
+    // writing nothing invalidates the whole shadow / refreshes every cached register
+    fn write_shadow(&self, action: &str, value: Vec<u8>) -> fuseable::Result<()> {
+        let name = String::from_utf8(value)?;
+        let name = name.trim();
+
+        let registers: Vec<&Register> = if name.is_empty() {
+            let cached = self.shadow.cached();
+            self.map.values().filter(|r| cached.contains(&r.address.base)).collect()
+        } else {
+            vec![self.map.get(name).ok_or_else(|| FuseableError::not_found(name))?]
+        };
+
+        match action {
+            "invalidate" => {
+                for register in registers {
+                    self.shadow.invalidate(&register.address.base);
+                }
+
+                Ok(())
+            }
+            "refresh" => {
+                for register in registers {
+                    self.shadow.invalidate(&register.address.base);
+                    self.read_register_value(register)?;
+                }
+
+                Ok(())
+            }
+            _ => Err(FuseableError::not_found(action)),
+        }
+    }
+
+    fn validate_staged(&self, kind: StagedKind, name: &str, value: &[u8]) -> fuseable::Result<()> {
+        let parsed = match kind {
+            StagedKind::Register => {
+                self.map.get(name).ok_or_else(|| FuseableError::not_found(name))?.parse_value(value)
+            }
+            StagedKind::Function => self
+                .functions
+                .get(name)
+                .ok_or_else(|| FuseableError::not_found(name))?
+                .parse_value(value),
+        };
+
+        parsed.map(|_| ())
+    }
+
+    // stages a write to the current transaction, a later write to the same register
+    // or function replaces the value, but keeps the position of the first write
+    fn stage(&mut self, kind: StagedKind, name: &str, value: Vec<u8>) -> fuseable::Result<()> {
+        self.validate_staged(kind, name, &value)?;
+
+        let transaction =
+            self.transaction.as_mut().ok_or_else(|| format_err!("no transaction in progress"))?;
+
+        match transaction.iter_mut().find(|w| w.kind == kind && w.name == name) {
+            Some(staged) => staged.value = value,
+            None => transaction.push(StagedWrite { kind, name: name.to_owned(), value }),
+        }
+
+        Ok(())
+    }
+
+    fn apply_staged(&self, staged: &StagedWrite) -> fuseable::Result<()> {
+        let value = staged.value.clone();
+
+        match staged.kind {
+            StagedKind::Register => self.map[&staged.name].write_value(
+                &mut std::iter::empty(),
+                value,
+                self,
+                &Request::default(),
+            ),
+            StagedKind::Function => self.functions[&staged.name].write_value(
+                &mut std::iter::empty(),
+                value,
+                self,
+                &Request::default(),
+            ),
+        }
+    }
+
+    // the raw value a staged write replaces, so it can be rolled back
+    fn read_staged(&self, staged: &StagedWrite) -> fuseable::Result<Vec<u8>> {
+        match staged.kind {
+            StagedKind::Register => self.read_register_value(&self.map[&staged.name]),
+            StagedKind::Function => self.read_function_value(&self.functions[&staged.name]),
+        }
+    }
+
+    fn restore_staged(&self, staged: &StagedWrite, old: Vec<u8>) -> fuseable::Result<()> {
+        match staged.kind {
+            StagedKind::Register => {
+                let register = &self.map[&staged.name];
+                self.write_address(&register.address, old.clone(), register.verify)?;
+
+                if !register.volatile {
+                    self.shadow.set(register.address.base.clone(), old);
+                }
+
+                Ok(())
+            }
+            StagedKind::Function => self.write_function_value(&self.functions[&staged.name], old),
+        }
+    }
+
+    // a register and a function of it written in the same transaction would
+    // overwrite each other depending on the order they were staged in
+    fn check_conflicts(&self, staged: &[StagedWrite]) -> fuseable::Result<()> {
+        let registers: Vec<_> =
+            staged.iter().filter(|w| w.kind == StagedKind::Register).map(|w| &w.name).collect();
+
+        for write in staged.iter().filter(|w| w.kind == StagedKind::Function) {
+            if let Some(register) =
+                self.functions[&write.name].register.as_ref().filter(|r| registers.contains(r))
+            {
+                return Err(FuseableError::invalid_value(format!(
+                    "{} conflicts with the write of register {}",
+                    write.to_string(),
+                    register
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // validates all staged writes first and only then applies them in the order
+    // they were staged, while holding the hold register if one is configured. if
+    // a write fails, the writes that were already applied are rolled back before
+    // the hold is released and the staged writes are kept, so the transaction can
+    // be committed again or aborted
+    fn commit(&mut self) -> fuseable::Result<()> {
+        let staged =
+            self.transaction.take().ok_or_else(|| format_err!("no transaction in progress"))?;
+
+        if let Err(e) = self.apply_transaction(&staged) {
+            self.transaction = Some(staged);
+            return Err(e);
+        }
+
+        self.check_checksum()
+    }
+
+    fn apply_transaction(&self, staged: &[StagedWrite]) -> fuseable::Result<()> {
+        for write in staged {
+            self.validate_staged(write.kind, &write.name, &write.value)
+                .map_err(|e| format_err!("could not validate {}: {}", write.to_string(), e))?;
+        }
+
+        self.check_conflicts(staged)?;
+
+        let old = staged
+            .iter()
+            .map(|write| self.read_staged(write))
+            .collect::<fuseable::Result<Vec<_>>>()?;
+
+        self.with_hold(|| {
+            for (applied, write) in staged.iter().enumerate() {
+                let error = match self.apply_staged(write) {
+                    Ok(()) => continue,
+                    Err(e) => e,
+                };
+
+                // the failed write is restored as well, it might have been written
+                // before failing (for example during verification). a write that
+                // can't be restored does not keep the others from being restored
+                let failed: Vec<String> = staged[..=applied]
+                    .iter()
+                    .zip(&old)
+                    .rev()
+                    .filter_map(|(write, old)| {
+                        self.restore_staged(write, old.clone())
+                            .err()
+                            .map(|e| format!("{} ({})", write.to_string(), e))
+                    })
+                    .collect();
+
+                return Err(if failed.is_empty() {
+                    format_err!(
+                        "could not apply {}, the transaction was rolled back: {}",
+                        write.to_string(),
+                        error
+                    )
+                } else {
+                    format_err!(
+                        "could not apply {} ({}), rolling back failed for {}",
+                        write.to_string(),
+                        error,
+                        failed.join(", ")
+                    )
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    // runs writes while holding the hold register if one is configured, so they
+    // take effect at the same time. nested calls keep holding it until the
+    // outermost one is done
+    fn with_hold<F>(&self, writes: F) -> fuseable::Result<()>
+    where
+        F: FnOnce() -> fuseable::Result<()>,
+    {
+        let hold = match self.hold {
+            Some(ref hold) => hold,
+            None => return writes(),
+        };
+
+        let outermost = self.holding.fetch_add(1, Ordering::SeqCst) == 0;
+
+        let held = if outermost { self.write_register(hold, 1) } else { Ok(()) };
+        let ret = held.and_then(|_| writes());
+
+        self.holding.fetch_sub(1, Ordering::SeqCst);
+
+        if outermost {
+            ret.and(self.write_register(hold, 0))
+        } else {
+            ret
+        }
+    }
+
+    fn contexts(&self) -> fuseable::Result<&Contexts> {
+        self.contexts.as_ref().ok_or_else(|| FuseableError::not_found("context"))
+    }
+
+    fn read_active_context(&self) -> fuseable::Result<String> {
+        let contexts = self.contexts()?;
+        let value = self.read_function(&contexts.select)?;
+
+        contexts
+            .contexts
+            .iter()
+            .find(|(_, c)| c.value == value.trim())
+            .map(|(name, _)| name.clone())
+            .ok_or_else(|| format_err!("{} selects no known context", value))
+    }
+
+    fn write_active_context(&self, value: Vec<u8>) -> fuseable::Result<()> {
+        let contexts = self.contexts()?;
+        let name = String::from_utf8(value)?;
+
+        self.write_function(&contexts.select, &contexts.context(name.trim())?.value)
+    }
+
+    // copies every parameter of one context to another, written as "<from> <to>"
+    fn copy_context(&self, value: Vec<u8>) -> fuseable::Result<()> {
+        let contexts = self.contexts()?;
+        let value = String::from_utf8(value)?;
+
+        let (from, to) = match value.split_whitespace().collect::<Vec<_>>()[..] {
+            [from, to] => (contexts.context(from)?, contexts.context(to)?),
+            _ => return Err(format_err!("expected \"<from> <to>\", got {}", value)),
+        };
+
+        self.with_hold(|| {
+            contexts.parameters.iter().try_for_each(|parameter| {
+                let source = &self.functions[&(parameter.to_owned() + &from.suffix)];
+                let target = &self.functions[&(parameter.to_owned() + &to.suffix)];
+
+                self.write_function_value(target, self.read_function_value(source)?)
+            })
+        })
+    }
+
+    fn write_transaction(&mut self, action: &str) -> fuseable::Result<()> {
+        match action {
+            "begin" => {
+                if self.transaction.is_some() {
+                    return Err(format_err!("there is already a transaction in progress"));
+                }
+
+                self.transaction = Some(Vec::new());
+
+                Ok(())
+            }
+            "commit" => self.commit(),
+            "abort" => self
+                .transaction
+                .take()
+                .ok_or_else(|| format_err!("no transaction in progress"))
+                .map(|_| ()),
+            _ => Err(FuseableError::unsupported("write", action)),
+        }
+    }
+
     fn read_register(&self, name: &str) -> fuseable::Result<String> {
-        self.map[name].read_value(&mut std::iter::empty(), &self.channel).map(|v| {
+        self.map[name].read_value(&mut std::iter::empty(), self, &Request::default()).map(|v| {
             match v {
                 Either::Right(s) => s,
                 _ => panic!("got directory entries from a register")
@@ -245,155 +929,529 @@ impl RegisterSetting {
     }
 
     fn write_register<T: ToString>(&self, name: &str, value: T) -> fuseable::Result<()> {
-        self.map[name].write_value(&mut std::iter::empty(), value.to_string().as_bytes().to_vec(), &self.channel)
+        self.map[name].write_value(
+            &mut std::iter::empty(),
+            value.to_string().as_bytes().to_vec(),
+            self,
+            &Request::default(),
+        )
     }
 
-    fn read_function(&self, name: &str) -> fuseable::Result<String>  {
-        self.functions[name].read_value(&mut std::iter::empty(), &self.channel).map(|v| {
-            match v {
+    fn read_function(&self, name: &str) -> fuseable::Result<String> {
+        self.functions[name].read_value(&mut std::iter::empty(), self, &Request::default()).map(
+            |v| match v {
                 Either::Right(s) => s,
-                _ => panic!("got directory entries from a register")
+                _ => panic!("got directory entries from a register"),
+            },
+        )
+    }
+
+    fn write_function<T: ToString>(&self, name: &str, value: T) -> fuseable::Result<()> {
+        self.functions[name].write_value(
+            &mut std::iter::empty(),
+            value.to_string().as_bytes().to_vec(),
+            self,
+            &Request::default(),
+        )
+    }
+
+    // numeric value of a (possibly mapped) function, for use by scripts
+    fn read_function_u64(&self, name: &str) -> fuseable::Result<u64> {
+        let value = self.read_function(name)?;
+        let value = value.trim();
+
+        let parsed = if value.starts_with("0x") {
+            u64::from_str_radix(&value[2..], 16)
+        } else {
+            value.parse()
+        };
+
+        parsed.map_err(|_| format_err!("function {} has the non numeric value {}", name, value))
+    }
+
+    // numeric value of a whole register, for use by scripts
+    fn read_register_u64(&self, name: &str) -> fuseable::Result<u64> {
+        let register = self.map.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+        let value = self.read_register_value(register)?;
+
+        Ok(value.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    // drops the shadowed value of the register of a function, so the next read
+    // gets it from the device, for example to see what the device made of a write
+    fn refresh_function(&self, name: &str) -> fuseable::Result<()> {
+        let function = self.functions.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+
+        if let Some(register) = function.register.as_ref().and_then(|name| self.map.get(name)) {
+            self.shadow.invalidate(&register.address.base);
+        }
+
+        Ok(())
+    }
+
+    // the name of the function for parameter in context, or parameter itself if
+    // no context is given or the parameter is shared by all contexts
+    fn context_function(&self, context: Option<&str>, parameter: &str) -> fuseable::Result<String> {
+        match context {
+            Some(context) => {
+                let contexts = self.contexts()?;
+
+                if contexts.parameters.iter().any(|p| p == parameter) {
+                    contexts.function(context, parameter)
+                } else {
+                    contexts.context(context).map(|_| parameter.to_owned())
+                }
             }
-        })
+            None => Ok(parameter.to_owned()),
+        }
+    }
+
+    fn check_functions(&self, context: Option<&str>, parameters: &[&str]) -> fuseable::Result<()> {
+        for parameter in parameters {
+            let name = self.context_function(context, parameter)?;
+
+            if !self.functions.contains_key(&name) {
+                return Err(format_err!("could not find function {}", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    // the largest value a function can hold
+    fn max_function_value(&self, name: &str) -> fuseable::Result<u64> {
+        let function = self.functions.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+
+        match function.addr.slice {
+            Some(ref slice) => Ok((1u64 << (slice.end - slice.start)) - 1),
+            None => Ok(u64::max_value()),
+        }
+    }
+
+    // reads the raw value of a watched path (map/<register> or functions/<function>),
+    // bypassing the shadow, reads of the same register are shared during a poll interval
+    fn read_watched(&self, path: &str) -> fuseable::Result<Vec<u8>> {
+        let read = |address: &Address| {
+            self.watches.read(address.to_string(), || self.channel.read_value(address))
+        };
+
+        match path.splitn(2, '/').collect::<Vec<_>>()[..] {
+            ["map", name] => {
+                read(&self.map.get(name).ok_or_else(|| FuseableError::not_found(name))?.address)
+            }
+            ["functions", name] => {
+                let function = self.functions.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+
+                match function.register.as_ref().and_then(|name| self.map.get(name)) {
+                    Some(register) => read(&register.address).map(|v| slice(v, &function.addr)),
+                    None => read(&function.addr),
+                }
+            }
+            _ => Err(unwatchable(path)),
+        }
     }
 
-    fn write_function<T: ToString>(&self, name: &str, value: T) -> fuseable::Result<()>  {
-        self.functions[name].write_value(&mut std::iter::empty(), value.to_string().as_bytes().to_vec(), &self.channel)
+    fn check_watch(&self, path: &str) -> fuseable::Result<()> {
+        match path.splitn(2, '/').collect::<Vec<_>>()[..] {
+            ["map", name] if self.map.contains_key(name) => Ok(()),
+            ["functions", name] if self.functions.contains_key(name) => Ok(()),
+            ["map", name] | ["functions", name] => Err(FuseableError::not_found(name)),
+            _ => Err(unwatchable(path)),
+        }
+    }
+
+    pub fn watch(&self, path: &str) -> fuseable::Result<()> {
+        self.check_watch(path)?;
+        self.watches.add(path);
+
+        Ok(())
+    }
+
+    // callback is called with the new raw value every time a poll notices a change
+    pub fn subscribe(&self, path: &str, callback: Callback) -> fuseable::Result<()> {
+        self.check_watch(path)?;
+        self.watches.subscribe(path, callback);
+
+        Ok(())
+    }
+
+    pub fn unwatch(&self, path: &str) -> fuseable::Result<()> {
+        if self.watches.remove(path) {
+            Ok(())
+        } else {
+            Err(FuseableError::not_found(path))
+        }
+    }
+
+    // polls every watched path that is due, returns the paths that changed
+    pub fn poll_watches(&self) -> fuseable::Result<Vec<String>> {
+        let mut changed = Vec::new();
+
+        for path in self.watches.watched() {
+            if self.watches.poll(&path, || self.read_watched(&path))? {
+                changed.push(path);
+            }
+        }
+
+        Ok(changed)
+    }
+
+    // changes the configuration of the channel, including its mode. the new channel
+    // keeps the mock mode and the write tracking of the old one. if it can't access
+    // every register this is reported, but the change is kept so it can be
+    // completed by further writes (for example the bus after switching the mode)
+    fn write_channel(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> fuseable::Result<()> {
+        let mock = self.channel.get_mock_mode();
+
+        self.channel.write(path, value)?;
+        self.channel.mock_mode(mock);
+
+        // the registers may now be backed by a different device
+        self.shadow.clear();
+        self.watches.forget_reads();
+
+        if let Some(ref checksum) = self.checksum {
+            self.channel.track_writes(checksum.clone())?;
+        }
+
+        self.map.values().try_for_each(|register| self.channel.check_address(&register.address))
+    }
+
+    // every line adds the watch for a path, lines starting with - remove it
+    fn write_watch(&self, value: Vec<u8>) -> fuseable::Result<()> {
+        let value = String::from_utf8(value)?;
+
+        for line in value.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if line.starts_with('-') {
+                self.unwatch(line[1..].trim())?;
+            } else {
+                self.watch(line)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl Fuseable for RegisterSetting {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<bool> {
+        self.is_dir_for(&Request::default(), path)
+    }
+
+    fn read(
+        &self,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<Either<Vec<String>, String>> {
+        self.read_for(&Request::default(), path)
+    }
+
+    fn write(
+        &mut self,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> fuseable::Result<()> {
+        self.write_for(&Request::default(), path, value)
+    }
+
+    fn is_dir_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<bool> {
         match path.next() {
             Some("channel") => self.channel.is_dir(path),
-            Some("map") => {
+            Some("map") => self.map.is_dir_with(self, request, path),
+            Some("functions") => self.functions.is_dir_with(self, request, path),
+            Some("shadow") => {
+                match (path.next(), path.next()) {
+                    (None, _) => Ok(true),
+                    (Some("invalidate"), None) | (Some("refresh"), None) => Ok(false),
+                    (Some(name), _) => Err(FuseableError::not_found(name)),
+                }
+            }
+            Some("watch") => {
+                match path.next() {
+                    Some(name) => Err(FuseableError::not_a_directory("watch", name)),
+                    None => Ok(false),
+                }
+            }
+            Some("transaction") => {
+                match (path.next(), path.next()) {
+                    (None, _) => Ok(true),
+                    (Some("begin"), None) | (Some("commit"), None) | (Some("abort"), None) | (Some("staged"), None) => Ok(false),
+                    (Some(name), _) => Err(FuseableError::not_found(name)),
+                }
+            }
+            Some("context") if self.contexts.is_some() => {
+                let contexts = self.contexts()?;
+
+                match (path.next(), path.next(), path.next()) {
+                    (None, _, _) => Ok(true),
+                    (Some("active"), None, _) | (Some("copy"), None, _) => Ok(false),
+                    (Some(context), None, _) => contexts.context(context).map(|_| true),
+                    (Some(context), Some(parameter), None) => contexts.function(context, parameter).map(|_| false),
+                    (Some(_), Some(parameter), Some(_)) => Err(FuseableError::not_a_directory(type_name(&self), parameter)),
+                }
+            }
+            Some("checksum") if self.checksum.is_some() => {
                 let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
 
-                match (reg_name, reg_field) {
-                    (Some(name), Some("value")) => {
-                        self.map.is_dir(&mut std::iter::once(name)).map(|_| false)
-                    },
-                    _ => self.map.is_dir(&mut path)
+                match (peek.next(), peek.next()) {
+                    (Some("check"), None) | (Some("sync"), None) => Ok(false),
+                    _ => self.checksum.as_ref().unwrap().deref().is_dir(&mut path),
                 }
             }
-            Some("functions") => {
+            Some(name) => Err(FuseableError::not_found(name)),
+            None => Ok(true),
+        }
+    }
+
+    // every access of the register set can end up on the bus, so requests whose
+    // deadline passed while waiting for the lock of the register set are not
+    // started
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<Either<Vec<String>, String>> {
+        request.check_deadline()?;
+
+        match path.next() {
+            Some("channel") => self.channel.read(path),
+            Some("map") => self.map.read_with(self, request, path),
+            Some("functions") => self.functions.read_with(self, request, path),
+            Some("shadow") => {
+                match path.next() {
+                    None => Ok(Either::Left(vec!["invalidate".to_owned(), "refresh".to_owned()])),
+                    Some(name) => Err(FuseableError::unsupported("read", name)),
+                }
+            }
+            Some("watch") => {
+                match path.next() {
+                    Some(name) => Err(FuseableError::not_a_directory("watch", name)),
+                    None => Ok(Either::Right(self.watches.watched().into_iter().map(|w| w + "\n").collect())),
+                }
+            }
+            Some("transaction") => {
+                match path.next() {
+                    None => Ok(Either::Left(vec!["begin".to_owned(), "commit".to_owned(), "abort".to_owned(), "staged".to_owned()])),
+                    Some("staged") => {
+                        let staged = self.transaction.iter().flatten().map(|w| w.to_string() + "\n").collect();
+                        Ok(Either::Right(staged))
+                    }
+                    Some(name) => Err(FuseableError::unsupported("read", name)),
+                }
+            }
+            Some("context") if self.contexts.is_some() => {
+                let contexts = self.contexts()?;
+
+                match (path.next(), path.next()) {
+                    (None, _) => {
+                        let mut entries: Vec<String> = contexts.contexts.keys().cloned().collect();
+                        entries.push("active".to_owned());
+                        entries.push("copy".to_owned());
+                        Ok(Either::Left(entries))
+                    }
+                    (Some("active"), None) => self.read_active_context().map(Either::Right),
+                    (Some("copy"), None) => Err(FuseableError::unsupported("read", "copy")),
+                    (Some(context), None) => {
+                        contexts.context(context).map(|_| Either::Left(contexts.parameters.clone()))
+                    }
+                    (Some(context), Some(parameter)) => {
+                        let function = contexts.function(context, parameter)?;
+
+                        self.functions[&function].read_value(path, self, request)
+                    }
+                }
+            }
+            Some("checksum") if self.checksum.is_some() => {
+                let checksum = self.checksum.as_ref().unwrap().deref();
                 let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
 
-                match (reg_name, reg_field) {
-                    (Some(name), Some("value")) => {
-                        self.functions.is_dir(&mut std::iter::once(name)).map(|_| false)
-                    },
-                    _ => self.functions.is_dir(&mut path)
+                match (peek.next(), peek.next()) {
+                    (None, _) => checksum.read(&mut path).and_then(|value| match value {
+                        Either::Left(mut dir_entries) => {
+                            dir_entries.push("check".to_owned());
+                            dir_entries.push("sync".to_owned());
+                            Ok(Either::Left(dir_entries))
+                        }
+                        Either::Right(_) => Err(format_err!("expected the entries of the checksum, but got file content")),
+                    }),
+                    // writing check compares the checksums, reading only reports the result
+                    (Some("check"), None) => Ok(Either::Right(checksum.last_check())),
+                    (Some("sync"), None) => Err(FuseableError::unsupported("read", "sync")),
+                    _ => checksum.read(&mut path),
                 }
             }
             Some(name) => Err(FuseableError::not_found(name)),
-            None => Ok(true),
+            None => {
+                let mut entries = vec!["channel".to_owned(), "map".to_owned(), "functions".to_owned(), "shadow".to_owned(), "transaction".to_owned(), "watch".to_owned()];
+
+                if self.contexts.is_some() {
+                    entries.push("context".to_owned());
+                }
+
+                if self.checksum.is_some() {
+                    entries.push("checksum".to_owned());
+                }
+
+                Ok(Either::Left(entries))
+            }
         }
     }
 
-    fn read(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Either<Vec<String>, String>> {
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> fuseable::Result<()> {
+        request.check_deadline()?;
+
         match path.next() {
-            Some("channel") => self.channel.read(path),
+            Some("channel") => self.write_channel(path, value),
+            // values written during a transaction are only staged
             Some("map") => {
                 let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
-
-                match (reg_name, reg_field) {
-                    (Some(_), None) => {
-                        self.map.read(&mut path).map(|value| {
-                            match value {
-                                Either::Left(mut dir_entries) => {
-                                    dir_entries.push("value".to_owned());
-                                    Either::Left(dir_entries)
-                                },
-                                Either::Right(_) => {
-                                    panic!("tought I would get directory entires, but got file content")
-                                }
-                            }
-                        })
+
+                match (peek.next(), peek.next(), peek.next()) {
+                    (Some(name), Some("value"), None) if self.transaction.is_some() => {
+                        self.stage(StagedKind::Register, name, value)
                     }
-                    (Some(name), Some("value")) => {
-                        self.map.get(name)
-                            .ok_or_else(|| FuseableError::not_found(name))?
-                            .read_value(&mut std::iter::empty(), &self.channel)
-                    },
-                    _ => self.map.read(&mut path)
+                    // only the value needs the register set, see FuseableContext
+                    (Some(_), Some("value"), None) => self.map.write_with(self, request, &mut path, value),
+                    _ => self.map.write_for(request, &mut path, value),
                 }
             }
             Some("functions") => {
                 let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
-
-                match (reg_name, reg_field) {
-                    (Some(_), None) => {
-                        self.functions.read(&mut path).map(|value| {
-                            match value {
-                                Either::Left(mut dir_entries) => {
-                                    dir_entries.push("value".to_owned());
-                                    Either::Left(dir_entries)
-                                },
-                                Either::Right(_) => {
-                                    panic!("tought I would get directory entires, but got file content")
-                                }
-                            }
-                        })
+
+                match (peek.next(), peek.next(), peek.next()) {
+                    (Some(name), Some("value"), None) if self.transaction.is_some() => {
+                        self.stage(StagedKind::Function, name, value)
                     }
-                    (Some(name), Some("value")) => {
-                        self.functions.get(name)
-                            .ok_or_else(|| FuseableError::not_found(name))?
-                            .read_value(&mut std::iter::empty(), &self.channel)
-                    },
-                    _ => self.functions.read(&mut path)
+                    (Some(_), Some("value"), None) => self.functions.write_with(self, request, &mut path, value),
+                    _ => self.functions.write_for(request, &mut path, value),
+                }
+            }
+            Some("shadow") => {
+                match path.next() {
+                    Some(action) => self.write_shadow(action, value),
+                    None => Err(FuseableError::unsupported("write", type_name(&self.shadow))),
+                }
+            }
+            Some("watch") => {
+                match path.next() {
+                    Some(name) => Err(FuseableError::not_a_directory("watch", name)),
+                    None => self.write_watch(value),
+                }
+            }
+            Some("transaction") => {
+                match path.next() {
+                    Some(action) => self.write_transaction(action),
+                    None => Err(FuseableError::unsupported("write", type_name(&self.transaction))),
+                }
+            }
+            Some("context") if self.contexts.is_some() => {
+                match (path.next(), path.next()) {
+                    (Some("active"), None) => self.write_active_context(value),
+                    (Some("copy"), None) => self.copy_context(value),
+                    (Some(context), Some(parameter)) => {
+                        let function = self.contexts()?.function(context, parameter)?;
+
+                        if self.transaction.is_some() {
+                            self.stage(StagedKind::Function, &function, value)
+                        } else {
+                            self.functions[&function].write_value(path, value, self, request)
+                        }
+                    }
+                    _ => Err(FuseableError::unsupported("write", type_name(&self.contexts))),
+                }
+            }
+            Some("checksum") if self.checksum.is_some() => {
+                match path.next() {
+                    Some("check") => self.check_checksum(),
+                    Some("sync") => self.sync_checksum(),
+                    _ => Err(FuseableError::unsupported("write", type_name(&self.checksum))),
                 }
             }
             Some(name) => Err(FuseableError::not_found(name)),
-            None => Ok(Either::Left(vec!["channel".to_owned(), "map".to_owned(), "functions".to_owned()]))
+            None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
 
-    fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> fuseable::Result<()> {
-        match path.next() {
-            Some("channel") => {
-                Err(FuseableError::unsupported("write", type_name(&self.channel)))
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Vec<(String, String)>> {
+        let path: Vec<_> = path.collect();
+
+        match path[..] {
+            ["map", name, "value"] => {
+                self.map.get(name).map(Register::xattrs).ok_or_else(|| FuseableError::not_found(name))
             }
-            Some("map") => {
-                let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
-
-                match (reg_name, reg_field) {
-                    (Some(name), Some("value")) => {
-                        self.map.get(name)
-                            .ok_or_else(|| FuseableError::not_found(name))?
-                            .write_value(&mut std::iter::empty(), value, &self.channel)
-                    },
-                    _ => self.map.write(&mut path, value)
-                }
+            ["functions", name, "value"] => {
+                self.functions.get(name).map(Function::xattrs).ok_or_else(|| FuseableError::not_found(name))
             }
-            Some("functions") => {
-                let (mut peek, mut path) = path.tee();
-                let reg_name = peek.next();
-                let reg_field = peek.next();
-
-                match (reg_name, reg_field) {
-                    (Some(name), Some("value")) => {
-                        self.functions.get(name)
-                            .ok_or_else(|| FuseableError::not_found(name))?
-                            .write_value(&mut std::iter::empty(), value, &self.channel)
-                    },
-                    _ => self.functions.write(&mut path, value)
+            ["context", context, parameter] if self.contexts.is_some() => {
+                Ok(self.functions[&self.contexts()?.function(context, parameter)?].xattrs())
+            }
+            ["checksum", "check"] | ["checksum", "sync"] => Ok(Vec::new()),
+            _ => match path.first() {
+                // the other fields of registers and functions, for example their doc
+                Some(&"map") => self.map.xattrs(&mut path[1..].iter().cloned()),
+                Some(&"functions") => self.functions.xattrs(&mut path[1..].iter().cloned()),
+                Some(&"checksum") if self.checksum.is_some() => {
+                    self.checksum.as_ref().unwrap().deref().xattrs(&mut path[1..].iter().cloned())
                 }
+                _ => Ok(Vec::new()),
+            },
+        }
+    }
+
+    // the last change noticed by polling, this does not poll itself, so a stat
+    // never accesses the bus
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Option<SystemTime>> {
+        match (path.next(), path.next(), path.next(), path.next()) {
+            (Some(kind @ "map"), Some(name), Some("value"), None)
+            | (Some(kind @ "functions"), Some(name), Some("value"), None) => {
+                Ok(self.watches.modified(&format!("{}/{}", kind, name)))
             }
-            Some(name) => Err(FuseableError::not_found(name)),
-            None => Err(FuseableError::unsupported("write", type_name(&self))),
+            (Some("context"), Some(context), Some(parameter), None) if self.contexts.is_some() => {
+                Ok(self.watches.modified(&format!("functions/{}", self.contexts()?.function(context, parameter)?)))
+            }
+            _ => Ok(None),
         }
     }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Permissions> {
+        let path: Vec<_> = path.collect();
+
+        let permissions = match path[..] {
+            ["map", name, "value"] => {
+                let register = self.map.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+                Permissions::access(register.access.readable(), register.access.writable())
+            }
+            ["functions", name, "value"] => {
+                let function = self.functions.get(name).ok_or_else(|| FuseableError::not_found(name))?;
+                Permissions::access(true, function.writable)
+            }
+            ["context", "active"] | ["checksum", "check"] | ["watch"] => Permissions::default(),
+            ["context", "copy"] | ["checksum", "sync"] | ["shadow", _] => Permissions::access(false, true),
+            ["context", context, parameter] if self.contexts.is_some() => {
+                let function = self.contexts()?.function(context, parameter)?;
+                Permissions::access(true, self.functions[&function].writable)
+            }
+            ["transaction", "staged"] => Permissions::access(true, false),
+            ["transaction", _] => Permissions::access(false, true),
+            _ => match path.first() {
+                Some(&"map") => self.map.permissions(&mut path[1..].iter().cloned())?,
+                Some(&"functions") => self.functions.permissions(&mut path[1..].iter().cloned())?,
+                Some(&"checksum") => Permissions::access(true, false),
+                Some(&"channel") => self.channel.permissions(&mut path[1..].iter().cloned())?,
+                _ => Permissions::default(),
+            },
+        };
+
+        Ok(permissions.below(Permissions::owned(self.owner, self.group)))
+    }
 }
 
 impl<'de> Deserialize<'de> for RegisterSetting {
@@ -410,6 +1468,8 @@ impl<'de> Deserialize<'de> for RegisterSetting {
             #[serde(default = "bool_false")]
             writable: bool,
             default: Option<u64>,
+            verify: Option<bool>,
+            unit: Option<String>,
         }
 
         #[derive(Debug, Deserialize)]
@@ -419,11 +1479,51 @@ impl<'de> Deserialize<'de> for RegisterSetting {
             map: HashMap<String, Register>,
             #[serde(deserialize_with = "by_path")]
             functions: HashMap<String, FunctionStringAddr>,
+            verify: Option<bool>,
+            hold: Option<String>,
+            checksum: Option<WriteChecksum>,
+            contexts: Option<Contexts>,
+            #[serde(default)]
+            watch: Watches,
+            #[serde(default, deserialize_with = "user_id")]
+            owner: Option<u32>,
+            #[serde(default, deserialize_with = "group_id")]
+            group: Option<u32>,
         }
 
         let settings = RegisterSettingConfig::deserialize(deserializer)?;
 
-        let RegisterSettingConfig { channel, map, functions } = settings;
+        let RegisterSettingConfig { channel, map, functions, verify, hold, checksum, mut contexts, watch, owner, group } = settings;
+
+        let checksum = match checksum {
+            Some(checksum) => {
+                if !map.contains_key(&checksum.register) {
+                    return Err(D::Error::custom(format!("could not find the checksum register {}", checksum.register)));
+                }
+
+                let checksum = Arc::new(checksum);
+
+                channel.track_writes(checksum.clone()).map_err(|e| D::Error::custom(e.to_string()))?;
+
+                Some(checksum)
+            }
+            None => None,
+        };
+
+        if let Some(ref hold) = hold {
+            if !map.contains_key(hold) {
+                return Err(D::Error::custom(format!("could not find the hold register {}", hold)));
+            }
+        }
+
+        for (name, reg) in &map {
+            channel.check_address(&reg.address).map_err(|e| {
+                D::Error::custom(format!(
+                    "register {} can not be accessed using channel {:?}: {}",
+                    name, channel, e
+                ))
+            })?;
+        }
 
         let functions = functions
             .into_iter()
@@ -435,6 +1535,14 @@ impl<'de> Deserialize<'de> for RegisterSetting {
                     ))
                 })?;
 
+                // functions based on a named register share its shadow, this only works if the
+                // register itself starts at bit zero, as the function slice is relative to that
+                let register = func.addr.split('[').next().map(str::trim).filter(|base| {
+                    map.get(*base)
+                        .map(|r| r.address.slice.as_ref().map(|s| s.start) == Some(0))
+                        .unwrap_or(false)
+                });
+
                 Ok((
                     name.clone(),
                     Function {
@@ -443,16 +1551,54 @@ impl<'de> Deserialize<'de> for RegisterSetting {
                         map: func.map,
                         default: func.default,
                         writable: func.writable,
+                        verify: func.verify,
+                        register: register.map(str::to_owned),
+                        unit: func.unit,
                     },
                 ))
             })
             .collect::<Result<HashMap<String, Function>, _>>()?;
 
-        Ok(RegisterSetting { channel, map, functions })
+        if let Some(ref mut contexts) = contexts {
+            if !functions.contains_key(&contexts.select) {
+                return Err(D::Error::custom(format!("could not find the context select function {}", contexts.select)));
+            }
+
+            contexts.find_parameters(&functions);
+        }
+
+        let register_setting = RegisterSetting {
+            channel,
+            map,
+            functions,
+            verify,
+            shadow: Shadow::default(),
+            hold,
+            holding: AtomicUsize::new(0),
+            transaction: None,
+            checksum,
+            contexts,
+            watches: watch,
+            owner,
+            group,
+        };
+
+        for path in &register_setting.watches.paths {
+            register_setting.watch(path).map_err(|e| D::Error::custom(format!("could not watch {}: {}", path, e)))?;
+        }
+
+        Ok(register_setting)
     }
 }
 
 #[derive(Debug, Serialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "RegisterSetting"
+))]
 pub struct Function {
     #[fuseable(ro)]
     addr: Address,
@@ -466,18 +1612,46 @@ pub struct Function {
     writable: bool,
     #[fuseable(ro)]
     default: Option<u64>,
+    #[fuseable(ro)]
+    verify: Option<bool>,
+    /// the named register this function is a slice of
+    #[fuseable(ro)]
+    register: Option<String>,
+    #[fuseable(ro)]
+    unit: Option<String>,
 }
 
 impl Function {
+    fn xattrs(&self) -> Vec<(String, String)> {
+        let access = if self.writable { "rw" } else { "ro" };
+        let mut xattrs = xattrs(&self.addr, &self.desc, self.default, &self.unit, access);
+
+        // the bits of the register the function is a slice of and the raw values
+        // that fit into them
+        if let Some(Slice { start, end }) = self.addr.slice {
+            let bits = u32::from(end.saturating_sub(start));
+            let max = 1u64.checked_shl(bits).map_or(u64::max_value(), |v| v - 1);
+
+            if let Some(mask) = max.checked_shl(u32::from(start)).filter(|_| u32::from(end) <= 64) {
+                xattrs.push(("mask".to_owned(), format!("{:#x}", mask)));
+            }
+
+            xattrs.push(("range".to_owned(), format!("0..{}", max)));
+        }
+
+        xattrs
+    }
+
     fn read_value(
         &self,
         path: &mut dyn Iterator<Item = &str>,
-        comm_channel: &CommunicationChannel
+        register_set: &RegisterSetting,
+        _request: &Request,
     ) -> fuseable::Result<Either<Vec<String>, String>> {
         match path.next() {
             Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
             None => {
-                let value = comm_channel.read_value(&self.addr)?;
+                let value = register_set.read_function_value(self)?;
 
                 match &self.map {
                     Some(map) => map.lookup(value).map(Either::Right),
@@ -487,80 +1661,214 @@ impl Function {
         }
     }
 
+    // encodes a value written to this function, returning the (optional) mask and
+    // the value
+    fn parse_value(&self, value: &[u8]) -> fuseable::Result<(Option<Vec<u8>>, Vec<u8>)> {
+        match &self.map {
+            Some(map) => {
+                let mut value = map.encode(String::from_utf8(value.to_vec())?)?;
+
+                // the keys of the map only have as many bytes as they were written with
+                while value.len() < self.addr.bytes().unwrap_or(0) {
+                    value.insert(0, 0);
+                }
+
+                Ok((None, value))
+            }
+            None => {
+                if let Some(width) = self.addr.bytes() {
+                    let (mask, mut value) = parse_num_mask(String::from_utf8_lossy(value))
+                        .map_err(|e| FuseableError::invalid_value(e.to_string()))?;
+
+                    if value.len() > width {
+                        return Err(FuseableError::invalid_value(format!(
+                            "value {:?} to write was longer ({}) than function {:?} with width of {}",
+                            value,
+                            value.len(),
+                            self,
+                            width
+                        )));
+                    }
+
+                    while value.len() < width {
+                        value.insert(0, 0);
+                    }
+
+                    let mask = mask.map(|mut mask| {
+                        while mask.len() < width {
+                            mask.insert(0, 0);
+                        }
+
+                        mask
+                    });
+
+                    Ok((mask, value))
+                } else {
+                    Err(format_err!("the function written to {:?} did not specify a width, don't know what to do", self))
+                }
+            }
+        }
+    }
+
     fn write_value(
         &self,
         path: &mut dyn Iterator<Item = &str>,
         value: Vec<u8>,
-        comm_channel: &CommunicationChannel
+        register_set: &RegisterSetting,
+        _request: &Request,
     ) -> fuseable::Result<()> {
         match path.next() {
             Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
             None => {
-                let value = match &self.map {
-                    Some(map) => map.encode(String::from_utf8(value)?)?,
-                    None =>  {
-                        if let Some(width) = self.addr.bytes() {
-                            let (mask, mut value) = parse_num_mask(String::from_utf8_lossy(&value))?;
-
-                            if value.len() > width as usize {
-                                return Err(format_err!("value {:?} to write was longer ({}) than function {:?} with width of {}", value, value.len(), self, width));
-                            }
-
-                            while value.len() < width as usize {
-                                value.insert(0, 0);
-                            }
-
-                            match mask {
-                                Some(mut mask) => {
-                                    while mask.len() < width as usize {
-                                        mask.insert(0, 0);
-                                    }
-
-                                    let current_value = comm_channel.read_value(&self.addr)?;
-
-                                    izip!(mask, value, current_value)
-                                        .map(|(m, val, cur)| (val & m) | (cur & !m))
-                                        .collect()
-                                }
-                                None => value,
-                            }
-                        } else {
-                            panic!("the function written to {:?} did not specify a width, don't know what to do", self)
-                        }
+                let value = match self.parse_value(&value)? {
+                    (Some(mask), value) => {
+                        let current_value = register_set.read_function_value(self)?;
+
+                        izip!(mask, value, current_value)
+                            .map(|(m, val, cur)| (val & m) | (cur & !m))
+                            .collect()
                     }
+                    (None, value) => value,
                 };
 
                 println!("encoded value: {:?}", value);
 
-                comm_channel.write_value(&self.addr, value)
+                register_set.write_function_value(self, value)
+            }
+        }
+    }
+}
+
+// scripts expose read and write as their value virtual field, using read_value
+// and write_value. they access the bus in several steps, so they check the
+// deadline of the request between them, but not once they started writing under
+// the hold, as releasing it early would apply a partial configuration
+trait Script: Debug + Send + Sync + Fuseable + FuseableContext<Camera> {
+    fn read(&self, cam: &Camera, request: &Request) -> fuseable::Result<String>;
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> fuseable::Result<()>;
+
+    // check that everything the script uses exists, this is called once the
+    // camera is loaded
+    fn check(&self, _cam: &Camera) -> fuseable::Result<()> { Ok(()) }
+
+    fn read_value(
+        &self,
+        path: &mut dyn Iterator<Item = &str>,
+        cam: &Camera,
+        request: &Request,
+    ) -> fuseable::Result<Either<Vec<String>, String>> {
+        match path.next() {
+            Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+            None => {
+                request.check_deadline()?;
+
+                Script::read(self, cam, request).map(Either::Right)
+            }
+        }
+    }
+
+    // writes lost during the script are noticed by checking the checksums afterwards
+    fn write_value(
+        &self,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+        cam: &Camera,
+        request: &Request,
+    ) -> fuseable::Result<()> {
+        match path.next() {
+            Some(s) => Err(FuseableError::not_a_directory(type_name(&self), s)),
+            None => {
+                request.check_deadline()?;
+                Script::write(self, cam, request, value)?;
+
+                cam.check_checksums()
             }
         }
     }
 }
 
-trait Script: Debug + Fuseable {
-    fn read(&self, cam: &Camera) -> fuseable::Result<String>;
-    fn write(&self, cam: &Camera, value: Vec<u8>) -> fuseable::Result<()>;
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+struct Bounds {
+    #[fuseable(ro)]
+    min: u64,
+    #[fuseable(ro)]
+    max: u64,
+}
+
+impl Bounds {
+    fn check(&self, what: &str, value: u64) -> fuseable::Result<()> {
+        if value < self.min || value > self.max {
+            Err(FuseableError::invalid_value(format!(
+                "{} {} is outside of {} to {}",
+                what, value, self.min, self.max
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+macro_rules! script_config {
+    ( $($struct:ident => $tag:tt),* ) => {
+        #[derive(Debug, Deserialize)]
+        #[serde(tag = "script")]
+        enum ScriptConfig {
+            $(
+                #[serde(rename = $tag)]
+                $struct($struct),
+            )*
+        }
+
+        impl ScriptConfig {
+            fn convert_to_script(self) -> Box<dyn Script> {
+                match self {
+                    $(
+                        ScriptConfig::$struct(script) => Box::new(script),
+                    )*
+                }
+            }
+        }
+    }
 }
 
+script_config!(
+    Roi => "roi",
+    FrameRate => "frame_rate",
+    Exposure => "exposure",
+    Shading => "shading",
+    TestPattern => "test_pattern",
+    OutputInterface => "output_interface"
+);
+
 
 #[derive(Debug, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
 struct Reset {}
 
 impl Script for Reset {
-    fn read(&self, cam: &Camera) -> fuseable::Result<String> {
+    fn read(&self, _cam: &Camera, _request: &Request) -> fuseable::Result<String> {
         Err(FuseableError::unsupported("read", type_name(&self)))
     }
 
-    fn write(&self, cam: &Camera, value: Vec<u8>) -> fuseable::Result<()> {
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> fuseable::Result<()> {
         println!("writing {:?}", value);
 
         let sensor_regs = cam.registers["sensor"].lock().unwrap();
         let sensor_io = cam.registers["sensor_io"].lock().unwrap();
 
+        request.check_deadline()?;
+
         sensor_io.write_register("reset", 1)?;
         std::thread::sleep(std::time::Duration::from_millis(10));
         sensor_io.write_register("reset", 0)?;
+        sensor_regs.shadow.clear();
+        sensor_regs.sync_checksum()?;
         sensor_regs.write_function("software_reset", 0)?;
         sensor_regs.write_function("stream", 1)?;
 
@@ -572,92 +1880,172 @@ impl Script for Reset {
 pub struct Camera {
     model: String,
     registers: HashMap<String, Arc<Mutex<RegisterSetting>>>,
-    scripts: HashMap<String, Box<dyn Script>>
+    scripts: HashMap<String, Box<dyn Script>>,
+    status: Option<Status>,
+    readout: Option<Readout>,
 }
 
 impl Fuseable for Camera {
     fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<bool> {
+        self.is_dir_for(&Request::default(), path)
+    }
+
+    fn read(
+        &self,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<Either<Vec<String>, String>> {
+        self.read_for(&Request::default(), path)
+    }
+
+    fn write(
+        &mut self,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> fuseable::Result<()> {
+        self.write_for(&Request::default(), path, value)
+    }
+
+    fn is_dir_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<bool> {
         match path.next() {
             Some("model") => self.model.is_dir(path),
-            Some("registers") => self.registers.is_dir(path),
-            Some("scripts") => {
-                let (mut peek, mut path) = path.tee();
-                let script_name = peek.next();
-                let script_field = peek.next();
-
-                match (script_name, script_field) {
-                    (Some(name), Some("value")) => {
-                        self.scripts.is_dir(&mut std::iter::once(name)).map(|_| false)
-                    },
-                    _ => self.scripts.is_dir(&mut path)
-                }
-            }
+            Some("registers") => self.registers.is_dir_for(request, path),
+            Some("status") if self.status.is_some() => self.status.as_ref().unwrap().is_dir(self, path),
+            Some("readout") if self.readout.is_some() => self.readout.as_ref().unwrap().is_dir(path),
+            Some("scripts") => self.scripts.is_dir_with(self, request, path),
             Some(name) => Err(FuseableError::not_found(name)),
             None => Ok(true),
         }
     }
 
-    fn read(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Either<Vec<String>, String>> {
+    fn read_for(
+        &self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> fuseable::Result<Either<Vec<String>, String>> {
         match path.next() {
             Some("model") => self.model.read(path),
-            Some("registers") => self.registers.read(path),
-            Some("scripts") => {
-                let (mut peek, mut path) = path.tee();
-                let script_name = peek.next();
-                let script_field = peek.next();
-
-                match (script_name, script_field) {
-                    (Some(_), None) => {
-                        self.scripts.read(&mut path).map(|value| {
-                            match value {
-                                Either::Left(mut dir_entries) => {
-                                    dir_entries.push("value".to_owned());
-                                    Either::Left(dir_entries)
-                                },
-                                Either::Right(_) => {
-                                    panic!("tought I would get directory entires, but got file content")
-                                }
-                            }
-                        })
-                    }
-                    (Some(name), Some("value")) => {
-                        Script::read(self.scripts.get(name)
-                                     .ok_or_else(|| FuseableError::not_found(name))?.deref(), &self)
-                            .map(|v| Either::Right(v))
-                    },
-                    _ => self.scripts.read(&mut path)
-                }
+            Some("registers") => self.registers.read_for(request, path),
+            Some("status") if self.status.is_some() => {
+                self.status.as_ref().unwrap().read(self, request, path)
+            }
+            Some("readout") if self.readout.is_some() => {
+                self.readout.as_ref().unwrap().read(self, request, path)
             }
+            Some("scripts") => self.scripts.read_with(self, request, path),
             Some(name) => Err(FuseableError::not_found(name)),
-            None => Ok(Either::Left(vec!["model".to_owned(), "registers".to_owned(), "scripts".to_owned()]))
+            None => {
+                let mut entries = vec!["model".to_owned(), "registers".to_owned(), "scripts".to_owned()];
+
+                if self.status.is_some() {
+                    entries.push("status".to_owned());
+                }
+
+                if self.readout.is_some() {
+                    entries.push("readout".to_owned());
+                }
+
+                Ok(Either::Left(entries))
+            }
         }
     }
 
-    fn write(&mut self, path: &mut dyn Iterator<Item = &str>, value: Vec<u8>) -> fuseable::Result<()> {
+    fn write_for(
+        &mut self,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> fuseable::Result<()> {
         match path.next() {
             Some("model") => {
                 Err(FuseableError::unsupported("write", "Camera.model"))
             }
             Some("registers") => {
-                self.registers.write(path, value)
+                self.registers.write_for(request, path, value)
+            }
+            Some("status") if self.status.is_some() => {
+                Err(FuseableError::unsupported("write", "Camera.status"))
+            }
+            Some("readout") if self.readout.is_some() => {
+                self.readout.as_ref().unwrap().write(self, request, path, value)?;
+
+                self.check_checksums()
             }
             Some("scripts") => {
                 let (mut peek, mut path) = path.tee();
-                let script_name = peek.next();
-                let script_field = peek.next();
 
-                match (script_name, script_field) {
-                    (Some(name), Some("value")) => {
-                        Script::write(self.scripts.get(name)
-                                     .ok_or_else(|| FuseableError::not_found(name))?.deref(), &self, value)
-                    },
-                    _ => self.scripts.write(&mut path, value)
+                match (peek.next(), peek.next(), peek.next()) {
+                    (Some(_), Some("value"), None) => self.scripts.write_with(self, request, &mut path, value),
+                    _ => self.scripts.write_for(request, &mut path, value),
                 }
             }
             Some(name) => Err(FuseableError::not_found(name)),
             None => Err(FuseableError::unsupported("write", type_name(&self))),
         }
     }
+
+    fn xattrs(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Vec<(String, String)>> {
+        match path.next() {
+            Some("registers") => self.registers.xattrs(path),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn modified(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Option<SystemTime>> {
+        match path.next() {
+            Some("registers") => self.registers.modified(path),
+            _ => Ok(None),
+        }
+    }
+
+    fn permissions(&self, path: &mut dyn Iterator<Item = &str>) -> fuseable::Result<Permissions> {
+        match path.next() {
+            Some("model") => Ok(Permissions::access(true, false)),
+            Some("registers") => self.registers.permissions(path),
+            _ => Ok(Permissions::default()),
+        }
+    }
+}
+
+pub struct Poller {
+    registers: Vec<(String, Arc<Mutex<RegisterSetting>>)>,
+    // measures the frame rate of the status, if it has a frame counter
+    frames: Option<FrameSampler>,
+    // the shortest poll interval of all register sets and the frame sampler
+    interval: Duration,
+}
+
+impl Poller {
+    // polls the watches of every register set, returns the changed paths relative
+    // to registers
+    pub fn poll(&self) -> fuseable::Result<Vec<String>> {
+        let mut changed = Vec::new();
+
+        for (name, register_set) in &self.registers {
+            let polled = register_set.lock().unwrap().poll_watches()?;
+            changed.extend(polled.into_iter().map(|path| format!("{}/{}", name, path)));
+        }
+
+        if let Some(ref frames) = self.frames {
+            frames.sample()?;
+        }
+
+        Ok(changed)
+    }
+
+    // polls forever, a failed poll is only logged so the next interval is tried again
+    pub fn run(&self) {
+        loop {
+            if let Err(e) = self.poll() {
+                warn!("polling the watched registers failed: {}", e);
+            }
+
+            thread::sleep(self.interval);
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for Camera {
@@ -669,15 +2057,89 @@ impl<'de> Deserialize<'de> for Camera {
         pub struct CameraWithoutScripts {
             model: String,
             registers: HashMap<String, Arc<Mutex<RegisterSetting>>>,
+            #[serde(default)]
+            scripts: HashMap<String, ScriptConfig>,
+            status: Option<Status>,
+            readout: Option<Readout>,
+        }
+
+        let CameraWithoutScripts { model, registers, scripts: script_configs, status, readout } = CameraWithoutScripts::deserialize(deserializer)?;
+
+        // recovering a channel locks the register set used for it, so recoveries
+        // leading back to a register set would deadlock (and keep each other alive)
+        let recovered_by: HashMap<&str, String> = registers
+            .iter()
+            .filter_map(|(name, rs)| {
+                rs.lock().unwrap().channel.policy().recovery.as_ref().map(|r| (name.as_str(), r.register_set.clone()))
+            })
+            .collect();
+
+        for name in recovered_by.keys() {
+            let mut chain = vec![*name];
+
+            while let Some(next) = recovered_by.get(chain[chain.len() - 1]) {
+                let cycle = chain.contains(&next.as_str());
+                chain.push(next);
+
+                if cycle {
+                    return Err(D::Error::custom(format!("the recovery of register sets is cyclic: {}", chain.join(" -> "))));
+                }
+            }
         }
 
-        let CameraWithoutScripts { model, registers } = CameraWithoutScripts::deserialize(deserializer)?;
+        for (name, register_set) in &registers {
+            let register_set = register_set.lock().unwrap();
+            let policy = register_set.channel.policy();
+
+            if let Some(BusRecovery { register_set: target_name, register, sequence, delay_ms }) = policy.recovery.clone() {
+                let target = registers.get(&target_name).ok_or_else(|| {
+                    D::Error::custom(format!("could not find register set {} used to recover {}", target_name, name))
+                })?.clone();
+
+                if !target.lock().unwrap().map.contains_key(&register) {
+                    return Err(D::Error::custom(format!("could not find register {} in {} used to recover {}", register, target_name, name)));
+                }
+
+                policy.set_recovery_hook(Box::new(move || -> fuseable::Result<()> {
+                    let target = target.lock().unwrap();
+
+                    for value in &sequence {
+                        target.write_register(&register, value)?;
+                        std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+                    }
+
+                    Ok(())
+                }));
+            }
+        }
 
         let mut scripts = HashMap::new();
         let reset: Box<dyn Script> = Box::new(Reset {});
         scripts.insert("reset".to_owned(), reset);
 
-        Ok(Camera { scripts, model, registers })
+        for (name, config) in script_configs {
+            if scripts.contains_key(&name) {
+                return Err(D::Error::custom(format!("there is already a script called {}", name)));
+            }
+
+            scripts.insert(name, config.convert_to_script());
+        }
+
+        let camera = Camera { scripts, model, registers, status, readout };
+
+        for (name, script) in &camera.scripts {
+            script.check(&camera).map_err(|e| D::Error::custom(format!("script {}: {}", name, e)))?;
+        }
+
+        if let Some(ref status) = camera.status {
+            status.check(&camera).map_err(|e| D::Error::custom(format!("status: {}", e)))?;
+        }
+
+        if let Some(ref readout) = camera.readout {
+            readout.check(&camera).map_err(|e| D::Error::custom(format!("readout: {}", e)))?;
+        }
+
+        Ok(camera)
     }
 }
 
@@ -687,4 +2149,121 @@ impl Camera {
             rs.lock().unwrap().channel.mock_mode(mock);
         }
     }
+
+    fn register_set(&self, name: &str) -> fuseable::Result<MutexGuard<'_, RegisterSetting>> {
+        self.registers.get(name).map(|rs| rs.lock().unwrap()).ok_or_else(|| FuseableError::not_found(name))
+    }
+
+    // path is relative to registers, for example sensor/map/frame_count
+    pub fn watch(&self, path: &str) -> fuseable::Result<()> {
+        let mut parts = path.splitn(2, '/');
+        let register_set = parts.next().unwrap_or_default();
+
+        self.register_set(register_set)?.watch(parts.next().unwrap_or_default())
+    }
+
+    pub fn subscribe(&self, path: &str, callback: Callback) -> fuseable::Result<()> {
+        let mut parts = path.splitn(2, '/');
+        let register_set = parts.next().unwrap_or_default();
+
+        self.register_set(register_set)?.subscribe(parts.next().unwrap_or_default(), callback)
+    }
+
+    // polls the watches of every register set, returns the changed paths relative
+    // to registers
+    pub fn poll_watches(&self) -> fuseable::Result<Vec<String>> { self.poller().poll() }
+
+    // polls the watches of the camera independently of it, so this can be run in
+    // its own thread after the camera was handed to the filesystem
+    pub fn poller(&self) -> Poller {
+        let registers: Vec<_> = self.registers.iter().map(|(name, rs)| (name.clone(), rs.clone())).collect();
+        let frames = self.status.as_ref().and_then(|status| status.sampler(self));
+        let interval = registers
+            .iter()
+            .map(|(_, rs)| rs.lock().unwrap().watches.interval())
+            .chain(frames.as_ref().map(FrameSampler::interval))
+            .min();
+
+        Poller { registers, frames, interval: interval.unwrap_or_else(|| Duration::from_millis(100)) }
+    }
+
+    // run after every script, so writes lost during a script are noticed
+    fn check_checksums(&self) -> fuseable::Result<()> {
+        self.registers.values().try_for_each(|rs| rs.lock().unwrap().check_checksum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::ar0330, StagedKind};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn transaction_test() {
+        let (cam, memories) = ar0330();
+        let memory = &memories["sensor"];
+        let mut regs = cam.register_set("sensor").unwrap();
+
+        let hold = regs.map["grouped_parameter_hold"].address.clone();
+        let x = regs.map["x_addr_start"].address.clone();
+        let y = regs.map["y_addr_start"].address.clone();
+
+        regs.write_transaction("begin").unwrap();
+        regs.stage(StagedKind::Function, "x_addr_start", b"100".to_vec()).unwrap();
+        regs.stage(StagedKind::Function, "y_addr_start", b"50".to_vec()).unwrap();
+        regs.stage(StagedKind::Function, "x_addr_start", b"102".to_vec()).unwrap();
+        assert!(regs.stage(StagedKind::Function, "x_addr_start", b"0x10000".to_vec()).is_err());
+        assert_eq!(memory.value(&x), 6);
+
+        // the write of x is rolled back and the staged writes are kept
+        memory.set_failing(&y, true);
+        assert!(regs.write_transaction("commit").is_err());
+        assert_eq!((memory.value(&x), memory.value(&y), memory.value(&hold)), (6, 124, 0));
+
+        let staged: Vec<_> = regs.transaction.iter().flatten().map(|w| w.to_string()).collect();
+        assert_eq!(staged, vec!["functions/x_addr_start = 102", "functions/y_addr_start = 50"]);
+
+        memory.set_failing(&y, false);
+        regs.write_transaction("commit").unwrap();
+        assert_eq!((memory.value(&x), memory.value(&y), memory.value(&hold)), (102, 50, 0));
+        assert!(regs.transaction.is_none());
+
+        let writes = memory.writes();
+        let expected = vec![&hold.base, &x.base, &y.base, &hold.base];
+        assert_eq!(writes[writes.len() - 4..].iter().collect::<Vec<_>>(), expected);
+
+        // a register and one of its functions can't be written together
+        regs.write_transaction("begin").unwrap();
+        regs.stage(StagedKind::Register, "x_addr_start", b"8".to_vec()).unwrap();
+        regs.stage(StagedKind::Function, "x_addr_start", b"10".to_vec()).unwrap();
+        assert!(regs.write_transaction("commit").is_err());
+        regs.write_transaction("abort").unwrap();
+        assert_eq!(memory.value(&x), 102);
+    }
+
+    #[test]
+    fn nested_hold_test() {
+        let (cam, memories) = ar0330();
+        let memory = &memories["sensor"];
+        let regs = cam.register_set("sensor").unwrap();
+        let hold = regs.map["grouped_parameter_hold"].address.clone();
+
+        regs.with_hold(|| {
+            regs.with_hold(|| regs.write_function("x_addr_start", 100))?;
+            assert_eq!(memory.value(&hold), 1);
+
+            regs.write_function("y_addr_start", 50)
+        })
+        .unwrap();
+
+        assert_eq!(memory.value(&hold), 0);
+        assert_eq!(memory.writes().iter().filter(|base| **base == hold.base).count(), 2);
+
+        // the hold is released if the writes fail
+        memory.set_failing(&regs.map["y_addr_start"].address, true);
+        assert!(regs.with_hold(|| regs.write_function("y_addr_start", 52)).is_err());
+        assert_eq!(memory.value(&hold), 0);
+        assert_eq!(regs.holding.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,366 @@
+use super::{Camera, RegisterSetting, Script};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const PARAMETERS: &[&str] = &[
+    "serial_format",
+    "data_format_bits_uncompressed",
+    "data_format_bits_compressed",
+    "compression_enable",
+];
+
+const HISPI_PARAMETERS: &[&str] = &["hispi_mode_sel", "streaming_mode", "output_msb_first"];
+
+// the timing functions that can be set for each interface
+const HISPI_TIMING: &[&str] = &["clock_del", "data0_del", "data1_del", "data2_del", "data3_del"];
+const MIPI_TIMING: &[&str] = &[
+    "frame_preamble",
+    "line_preamble",
+    "mipi_timing_0_t_hs_prepare",
+    "t_hs_zero",
+    "t_hs_trail",
+    "t_clk_trail",
+    "t_clk_zero",
+    "t_clk_pre",
+    "t_clk_post",
+    "t_hs_exit",
+    "t_bgap",
+    "t_wake_up",
+    "t_lpx",
+    "t_init",
+    "cont_tx_clk",
+    "mipi_heavy_lp_load",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Interface {
+    Hispi,
+    Mipi,
+}
+
+impl Interface {
+    fn name(self) -> &'static str {
+        match self {
+            Interface::Hispi => "hispi",
+            Interface::Mipi => "mipi",
+        }
+    }
+
+    fn lanes(self) -> &'static [u64] {
+        match self {
+            Interface::Hispi => &[2, 4],
+            Interface::Mipi => &[1, 2, 4],
+        }
+    }
+
+    fn timing(self) -> &'static [&'static str] {
+        match self {
+            Interface::Hispi => HISPI_TIMING,
+            Interface::Mipi => MIPI_TIMING,
+        }
+    }
+}
+
+// the names used by the valuemap of serial_format, for example 4_lane_hispi
+fn serial_format(interface: Interface, lanes: u64) -> String {
+    format!("{}_lane_{}", lanes, interface.name())
+}
+
+fn parse_serial_format(value: &str) -> Result<(Interface, u64)> {
+    let mut parts = value.trim().splitn(3, '_');
+
+    match (parts.next().map(str::parse::<u64>), parts.next(), parts.next()) {
+        (Some(Ok(lanes)), Some("lane"), Some("hispi")) => Ok((Interface::Hispi, lanes)),
+        (Some(Ok(lanes)), Some("lane"), Some("mipi")) => Ok((Interface::Mipi, lanes)),
+        _ => Err(FuseableError::invalid_value(format!("unknown serial format {}", value.trim()))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Protocol {
+    HispiStreamingS,
+    HispiStreamingSp,
+    HispiPacketizedSp,
+    Mipi,
+}
+
+impl Protocol {
+    fn interface(self) -> Interface {
+        match self {
+            Protocol::Mipi => Interface::Mipi,
+            _ => Interface::Hispi,
+        }
+    }
+}
+
+// the description of the output interface, this is what is written to and read
+// from value
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Description {
+    protocol: Protocol,
+    lanes: u64,
+    bits: u64,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compressed_bits: Option<u64>,
+    // only used by hispi
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    msb_first: Option<bool>,
+    // timing functions to change, the ones not given are left as they are
+    #[serde(default)]
+    timing: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+struct Compression {
+    #[fuseable(ro)]
+    from: u64,
+    #[fuseable(ro)]
+    to: u64,
+}
+
+// configures the serial output from a description like
+//   protocol: hispi_packetized_sp
+//   lanes: 4
+//   bits: 12
+// the interface type (hispi or mipi) is fixed by the sensor, so only the
+// protocols of that interface can be selected
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct OutputInterface {
+    #[fuseable(ro)]
+    register_set: String,
+    // the supported uncompressed bit depths
+    #[fuseable(ro)]
+    bits: Vec<u64>,
+    #[fuseable(ro)]
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+impl OutputInterface {
+    fn validate(&self, regs: &RegisterSetting, description: &Description) -> Result<()> {
+        let interface = description.protocol.interface();
+
+        // the upper byte of serial_format is read only and tells which interface
+        // the sensor has
+        let (current, _) = parse_serial_format(&regs.read_function("serial_format")?)?;
+
+        if current != interface {
+            return Err(FuseableError::invalid_value(format!(
+                "the sensor only has a {} interface, {:?} is not possible",
+                current.name(),
+                description.protocol
+            )));
+        }
+
+        if !interface.lanes().contains(&description.lanes) {
+            return Err(FuseableError::invalid_value(format!(
+                "{} supports {:?} lanes, got {}",
+                interface.name(),
+                interface.lanes(),
+                description.lanes
+            )));
+        }
+
+        if !self.bits.contains(&description.bits) {
+            return Err(FuseableError::invalid_value(format!(
+                "supported bit depths are {:?}, got {}",
+                self.bits, description.bits
+            )));
+        }
+
+        if let Some(compressed) = description.compressed_bits {
+            match self.compression {
+                Some(Compression { from, to }) if from == description.bits && to == compressed => {}
+                Some(Compression { from, to }) => {
+                    return Err(FuseableError::invalid_value(format!(
+                        "only compression from {} to {} bits is supported, got {} to {}",
+                        from, to, description.bits, compressed
+                    )))
+                }
+                None => {
+                    return Err(FuseableError::invalid_value(
+                        "the sensor does not support compression",
+                    ))
+                }
+            }
+        }
+
+        if interface == Interface::Mipi && description.msb_first.is_some() {
+            return Err(FuseableError::invalid_value("msb_first can only be set for hispi"));
+        }
+
+        for (name, value) in &description.timing {
+            if !interface.timing().contains(&name.as_str()) {
+                return Err(FuseableError::invalid_value(format!(
+                    "unknown {} timing {}, expected one of {:?}",
+                    interface.name(),
+                    name,
+                    interface.timing()
+                )));
+            }
+
+            let max = regs.max_function_value(name)?;
+
+            if *value > max {
+                return Err(FuseableError::invalid_value(format!(
+                    "{} is larger than the maximum of {}",
+                    name, max
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_protocol(&self, regs: &RegisterSetting, interface: Interface) -> Result<Protocol> {
+        if interface == Interface::Mipi {
+            return Ok(Protocol::Mipi);
+        }
+
+        match regs.read_function("hispi_mode_sel")?.trim() {
+            "streaming_s" => Ok(Protocol::HispiStreamingS),
+            "sp" => match regs.read_function("streaming_mode")?.trim() {
+                "streaming_sp" => Ok(Protocol::HispiStreamingSp),
+                "packetized_sp" => Ok(Protocol::HispiPacketizedSp),
+                mode => Err(format_err!("unknown streaming mode {}", mode)),
+            },
+            mode => Err(format_err!("unknown hispi mode {}", mode)),
+        }
+    }
+}
+
+impl Script for OutputInterface {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        let (interface, lanes) = parse_serial_format(&regs.read_function("serial_format")?)?;
+        let protocol = self.read_protocol(&regs, interface)?;
+
+        let compressed_bits = if regs.read_function_u64("compression_enable")? != 0 {
+            Some(regs.read_function_u64("data_format_bits_compressed")?)
+        } else {
+            None
+        };
+
+        let msb_first = match interface {
+            Interface::Hispi => Some(regs.read_function_u64("output_msb_first")? != 0),
+            Interface::Mipi => None,
+        };
+
+        let timing = interface
+            .timing()
+            .iter()
+            .map(|name| Ok((name.to_string(), regs.read_function_u64(name)?)))
+            .collect::<Result<_>>()?;
+
+        let description = Description {
+            protocol,
+            lanes,
+            bits: regs.read_function_u64("data_format_bits_uncompressed")?,
+            compressed_bits,
+            msb_first,
+            timing,
+        };
+
+        serde_yaml::to_string(&description).map_err(|e| e.into())
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let description: Description = serde_yaml::from_slice(&value)?;
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        self.validate(&regs, &description)?;
+        request.check_deadline()?;
+
+        let interface = description.protocol.interface();
+
+        regs.with_hold(|| {
+            regs.write_function("serial_format", serial_format(interface, description.lanes))?;
+
+            regs.write_function("data_format_bits_uncompressed", description.bits)?;
+            regs.write_function(
+                "data_format_bits_compressed",
+                description.compressed_bits.unwrap_or(description.bits),
+            )?;
+            regs.write_function("compression_enable", description.compressed_bits.is_some() as u8)?;
+
+            match description.protocol {
+                Protocol::HispiStreamingS => {
+                    regs.write_function("hispi_mode_sel", "streaming_s")?
+                }
+                Protocol::HispiStreamingSp => {
+                    regs.write_function("hispi_mode_sel", "sp")?;
+                    regs.write_function("streaming_mode", "streaming_sp")?;
+                }
+                Protocol::HispiPacketizedSp => {
+                    regs.write_function("hispi_mode_sel", "sp")?;
+                    regs.write_function("streaming_mode", "packetized_sp")?;
+                }
+                Protocol::Mipi => {}
+            }
+
+            if let Some(msb_first) = description.msb_first {
+                regs.write_function("output_msb_first", msb_first as u8)?;
+            }
+
+            description.timing.iter().try_for_each(|(name, value)| regs.write_function(name, value))
+        })
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        // which interface the sensor has is only known once it is powered up
+        regs.check_functions(None, PARAMETERS)?;
+        regs.check_functions(None, HISPI_PARAMETERS)?;
+        regs.check_functions(None, HISPI_TIMING)?;
+        regs.check_functions(None, MIPI_TIMING)?;
+
+        if let Some(ref compression) = self.compression {
+            if !self.bits.contains(&compression.from) {
+                return Err(format_err!(
+                    "compression from {} bits is not one of the supported bit depths {:?}",
+                    compression.from,
+                    self.bits
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_serial_format, serial_format, Interface};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn serial_format_test() {
+        for &interface in &[Interface::Hispi, Interface::Mipi] {
+            for &lanes in interface.lanes() {
+                let format = serial_format(interface, lanes);
+                assert_eq!(parse_serial_format(&format).unwrap(), (interface, lanes));
+            }
+        }
+
+        assert_eq!(serial_format(Interface::Hispi, 4), "4_lane_hispi");
+        assert!(parse_serial_format("0x0405").is_err());
+        assert!(parse_serial_format("4_lane_lvds").is_err());
+    }
+}
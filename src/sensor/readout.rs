@@ -0,0 +1,428 @@
+use super::{Bounds, Camera, RegisterSetting};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::serde_util::{bool_false, one};
+
+const ENTRIES: &[&str] = &["flip", "mirror", "binning", "skip"];
+
+const PARAMETERS: &[&str] = &[
+    "x_addr_start",
+    "x_addr_end",
+    "y_addr_start",
+    "y_addr_end",
+    "x_odd_inc",
+    "y_odd_inc",
+    "read_mode_col_bin",
+    "read_mode_row_bin",
+];
+
+const ORIENTATION: &[&str] = &["vert_flip", "horiz_mirror"];
+
+const BAYER_PATTERNS: &[&str] = &["RGGB", "GRBG", "GBRG", "BGGR"];
+
+// the pattern seen when the readout starts one column and / or one row later
+fn shift_bayer_pattern(base: &str, column: bool, row: bool) -> Result<String> {
+    if !BAYER_PATTERNS.contains(&base) {
+        return Err(format_err!(
+            "unknown bayer pattern {}, expected one of {:?}",
+            base,
+            BAYER_PATTERNS
+        ));
+    }
+
+    let base = base.as_bytes();
+
+    Ok((0..2)
+        .flat_map(|r| (0..2).map(move |c| (r, c)))
+        .map(|(r, c)| base[((r + row as usize) % 2) * 2 + (c + column as usize) % 2] as char)
+        .collect())
+}
+
+// the readout options of a register set, every entry is a directory containing
+// value and the options value can be set to:
+//   flip, mirror: 0 or 1
+//   binning: 1x1, 2x2, ...
+//   skip: 1, 2, ...
+//   bayer_pattern: read only, the color filter pattern of the output
+// the window is adjusted, so that it stays a multiple of the new factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Readout {
+    register_set: String,
+    #[serde(default)]
+    context: Option<String>,
+    // the supported binning and skipping factors, 1 is always supported
+    binning: Vec<u64>,
+    skip: Vec<u64>,
+    // the width and height of the window have to be a multiple of this times the
+    // factor
+    #[serde(default = "one")]
+    align: u64,
+    // move the window by one pixel when flipping or mirroring, so the color of
+    // the first pixel stays the same
+    #[serde(default = "bool_false")]
+    keep_bayer_phase: bool,
+    columns: Bounds,
+    rows: Bounds,
+    // the color filter pattern of the pixel array, starting at column 0 and
+    // row 0 without flipping or mirroring
+    #[serde(default)]
+    bayer_pattern: Option<String>,
+}
+
+impl Readout {
+    fn function(&self, regs: &RegisterSetting, parameter: &str) -> Result<String> {
+        regs.context_function(self.context.as_ref().map(String::as_str), parameter)
+    }
+
+    fn read_parameter(&self, regs: &RegisterSetting, parameter: &str) -> Result<u64> {
+        regs.read_function_u64(&self.function(regs, parameter)?)
+    }
+
+    fn write_parameter(&self, regs: &RegisterSetting, parameter: &str, value: u64) -> Result<()> {
+        regs.write_function(&self.function(regs, parameter)?, value)
+    }
+
+    fn entries(&self) -> Vec<&'static str> {
+        let mut entries = ENTRIES.to_vec();
+
+        if self.bayer_pattern.is_some() {
+            entries.push("bayer_pattern");
+        }
+
+        entries
+    }
+
+    fn entry(&self, name: &str) -> Result<&'static str> {
+        self.entries()
+            .into_iter()
+            .find(|e| *e == name)
+            .ok_or_else(|| FuseableError::not_found(name))
+    }
+
+    fn options(&self, entry: &str) -> Vec<String> {
+        let factors = |supported: &[u64]| {
+            let mut factors = vec![1];
+            factors.extend(supported.iter().filter(|f| **f != 1));
+            factors
+        };
+
+        match entry {
+            "flip" | "mirror" => vec!["0".to_owned(), "1".to_owned()],
+            "binning" => factors(&self.binning).iter().map(|f| format!("{}x{}", f, f)).collect(),
+            "bayer_pattern" => BAYER_PATTERNS.iter().map(|p| p.to_string()).collect(),
+            _ => factors(&self.skip).iter().map(u64::to_string).collect(),
+        }
+    }
+
+    // (x, y) factor and whether binning is enabled
+    fn read_factors(&self, regs: &RegisterSetting) -> Result<(u64, u64, bool)> {
+        let x = (self.read_parameter(regs, "x_odd_inc")? + 1) / 2;
+        let y = (self.read_parameter(regs, "y_odd_inc")? + 1) / 2;
+        let bin = self.read_parameter(regs, "read_mode_col_bin")? != 0
+            || self.read_parameter(regs, "read_mode_row_bin")? != 0;
+
+        Ok((x, y, bin))
+    }
+
+    // the readout starts at the end of the window when mirroring or flipping, the
+    // window always has an even size, so this is one column or row off of the
+    // start of the window
+    fn read_bayer_pattern(&self, regs: &RegisterSetting, base: &str) -> Result<String> {
+        let column =
+            self.read_parameter(regs, "x_addr_start")? + regs.read_function_u64("horiz_mirror")?;
+        let row =
+            self.read_parameter(regs, "y_addr_start")? + regs.read_function_u64("vert_flip")?;
+
+        shift_bayer_pattern(base, column % 2 != 0, row % 2 != 0)
+    }
+
+    fn read_entry(&self, regs: &RegisterSetting, entry: &str) -> Result<String> {
+        match (entry, &self.bayer_pattern) {
+            ("bayer_pattern", Some(base)) => return self.read_bayer_pattern(regs, base),
+            ("bayer_pattern", None) => return Err(FuseableError::not_found(entry)),
+            _ => {}
+        }
+
+        match entry {
+            "flip" => regs.read_function_u64("vert_flip").map(|v| v.to_string()),
+            "mirror" => regs.read_function_u64("horiz_mirror").map(|v| v.to_string()),
+            _ => {
+                let (x, y, bin) = self.read_factors(regs)?;
+
+                Ok(match entry {
+                    "binning" if bin => format!("{}x{}", x, y),
+                    "binning" => "1x1".to_owned(),
+                    _ if bin => "1".to_owned(),
+                    _ if x == y => x.to_string(),
+                    _ => format!("{}x{}", x, y),
+                })
+            }
+        }
+    }
+
+    fn write_orientation(&self, regs: &RegisterSetting, function: &str, value: &str) -> Result<()> {
+        let enable = match value {
+            "0" => 0,
+            "1" => 1,
+            _ => {
+                return Err(FuseableError::invalid_value(format!("expected 0 or 1, got {}", value)))
+            }
+        };
+
+        if !self.keep_bayer_phase || regs.read_function_u64(function)? == enable {
+            return regs.write_function(function, enable);
+        }
+
+        let (start, end, bounds) = match function {
+            "horiz_mirror" => ("x_addr_start", "x_addr_end", &self.columns),
+            _ => ("y_addr_start", "y_addr_end", &self.rows),
+        };
+
+        let first = self.read_parameter(regs, start)?;
+        let last = self.read_parameter(regs, end)?;
+
+        // the phase only depends on the parity of the start, so moving in either
+        // direction works
+        let (first, last) = if last < bounds.max {
+            (first + 1, last + 1)
+        } else if first > bounds.min {
+            (first - 1, last - 1)
+        } else {
+            return Err(FuseableError::invalid_value(format!(
+                "the window covers all of {} to {}, it cannot be moved to keep the bayer phase",
+                bounds.min, bounds.max
+            )));
+        };
+
+        regs.with_hold(|| {
+            self.write_parameter(regs, start, first)?;
+            self.write_parameter(regs, end, last)?;
+            regs.write_function(function, enable)
+        })
+    }
+
+    // shrinks the window to a multiple of the factor
+    fn fit(&self, regs: &RegisterSetting, start: &str, end: &str, factor: u64) -> Result<u64> {
+        let first = self.read_parameter(regs, start)?;
+        let size = (self.read_parameter(regs, end)? + 1).saturating_sub(first);
+        let size = size - size % (self.align * factor);
+
+        if size == 0 {
+            return Err(FuseableError::invalid_value(format!(
+                "the window is too small for a factor of {}",
+                factor
+            )));
+        }
+
+        Ok(first + size - 1)
+    }
+
+    fn write_factor(&self, regs: &RegisterSetting, factor: u64, bin: bool) -> Result<()> {
+        let supported = if bin { &self.binning } else { &self.skip };
+
+        if factor != 1 && !supported.contains(&factor) {
+            return Err(FuseableError::invalid_value(format!(
+                "supported factors are {:?}, got {}",
+                supported, factor
+            )));
+        }
+
+        let bin = bin && factor > 1;
+
+        // row binning only works with an even start row
+        if bin && self.read_parameter(regs, "y_addr_start")? % 2 != 0 {
+            return Err(FuseableError::invalid_value("binning needs y_addr_start to be even"));
+        }
+
+        let x_end = self.fit(regs, "x_addr_start", "x_addr_end", factor)?;
+        let y_end = self.fit(regs, "y_addr_start", "y_addr_end", factor)?;
+
+        regs.with_hold(|| {
+            self.write_parameter(regs, "x_addr_end", x_end)?;
+            self.write_parameter(regs, "y_addr_end", y_end)?;
+            self.write_parameter(regs, "x_odd_inc", 2 * factor - 1)?;
+            self.write_parameter(regs, "y_odd_inc", 2 * factor - 1)?;
+            self.write_parameter(regs, "read_mode_col_bin", bin as u64)?;
+            self.write_parameter(regs, "read_mode_row_bin", bin as u64)
+        })
+    }
+
+    fn write_entry(&self, regs: &RegisterSetting, entry: &str, value: &str) -> Result<()> {
+        let parse = |value: &str| {
+            value.parse::<u64>().map_err(|e| {
+                FuseableError::invalid_value(format!("could not parse {}: {}", value, e))
+            })
+        };
+
+        match entry {
+            "flip" => self.write_orientation(regs, "vert_flip", value),
+            "mirror" => self.write_orientation(regs, "horiz_mirror", value),
+            "binning" => {
+                let mut factors = value.splitn(2, 'x');
+
+                match (factors.next().map(parse), factors.next().map(parse)) {
+                    (Some(Ok(x)), Some(Ok(y))) if x == y => self.write_factor(regs, x, true),
+                    (Some(Ok(_)), Some(Ok(_))) => Err(FuseableError::invalid_value(format!(
+                        "only square binning is supported, got {}",
+                        value
+                    ))),
+                    _ => Err(FuseableError::invalid_value(format!(
+                        "expected binning as NxN, got {}",
+                        value
+                    ))),
+                }
+            }
+            _ => self.write_factor(regs, parse(value)?, false),
+        }
+    }
+
+    pub fn is_dir(&self, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        match (path.next(), path.next()) {
+            (None, _) => Ok(true),
+            (Some(entry), None) => self.entry(entry).map(|_| true),
+            (Some(entry), Some("value")) | (Some(entry), Some("options")) => {
+                self.entry(entry).map(|_| false)
+            }
+            (Some(_), Some(name)) => Err(FuseableError::not_found(name)),
+        }
+    }
+
+    pub fn read(
+        &self,
+        cam: &Camera,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        match (path.next(), path.next()) {
+            (None, _) => Ok(Either::Left(self.entries().iter().map(|e| e.to_string()).collect())),
+            (Some(entry), None) => self
+                .entry(entry)
+                .map(|_| Either::Left(vec!["value".to_owned(), "options".to_owned()])),
+            (Some(entry), Some("value")) => {
+                let regs = cam.register_set(&self.register_set)?;
+                request.check_deadline()?;
+
+                self.read_entry(&regs, self.entry(entry)?).map(Either::Right)
+            }
+            (Some(entry), Some("options")) => {
+                Ok(Either::Right(self.options(self.entry(entry)?).join("\n")))
+            }
+            (Some(_), Some(name)) => Err(FuseableError::not_found(name)),
+        }
+    }
+
+    pub fn write(
+        &self,
+        cam: &Camera,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        match (path.next(), path.next()) {
+            (Some("bayer_pattern"), Some("value")) => {
+                Err(FuseableError::unsupported("write", "Readout.bayer_pattern"))
+            }
+            (Some(entry), Some("value")) => {
+                let regs = cam.register_set(&self.register_set)?;
+                request.check_deadline()?;
+                let value = String::from_utf8(value)?;
+
+                self.write_entry(&regs, self.entry(entry)?, value.trim())
+            }
+            (Some(entry), Some("options")) => {
+                Err(FuseableError::unsupported("write", format!("Readout.{}.options", entry)))
+            }
+            _ => Err(FuseableError::unsupported("write", "Readout")),
+        }
+    }
+
+    pub fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        regs.check_functions(self.context.as_ref().map(String::as_str), PARAMETERS)?;
+        regs.check_functions(None, ORIENTATION)?;
+
+        if let Some(ref base) = self.bayer_pattern {
+            shift_bayer_pattern(base, false, false)?;
+        }
+
+        if self.align == 0 {
+            return Err(format_err!("align has to be at least 1"));
+        }
+
+        for factor in self.binning.iter().chain(&self.skip) {
+            // x_odd_inc is three bits wide
+            if *factor == 0 || 2 * factor - 1 > regs.max_function_value("x_odd_inc")? {
+                return Err(format_err!("factor {} cannot be set with x_odd_inc", factor));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shift_bayer_pattern;
+    use crate::sensor::testing::ar0330;
+    use fuseable::{Either, Request};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn bayer_pattern_test() {
+        assert_eq!(shift_bayer_pattern("GRBG", false, false).unwrap(), "GRBG");
+        assert_eq!(shift_bayer_pattern("GRBG", true, false).unwrap(), "RGGB");
+        assert_eq!(shift_bayer_pattern("GRBG", false, true).unwrap(), "BGGR");
+        assert_eq!(shift_bayer_pattern("GRBG", true, true).unwrap(), "GBRG");
+        assert_eq!(shift_bayer_pattern("RGGB", true, true).unwrap(), "BGGR");
+        assert!(shift_bayer_pattern("RGBG", false, false).is_err());
+    }
+
+    #[test]
+    fn write_factor_test() {
+        let (cam, memories) = ar0330();
+        let readout = cam.readout.as_ref().unwrap();
+
+        let read = |entry: &str| match readout.read(
+            &cam,
+            &Request::default(),
+            &mut vec![entry, "value"].into_iter(),
+        ) {
+            Ok(Either::Right(value)) => value,
+            result => panic!("could not read {}: {:?}", entry, result),
+        };
+        let write = |entry: &str, value: &str| {
+            readout.write(
+                &cam,
+                &Request::default(),
+                &mut vec![entry, "value"].into_iter(),
+                value.into(),
+            )
+        };
+        let register = |name: &str| {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].value(&regs.map[name].address)
+        };
+
+        write("binning", "2x2").unwrap();
+        assert_eq!(read("binning"), "2x2");
+        assert_eq!(read("skip"), "1");
+        assert_eq!(register("x_odd_inc"), 3);
+        assert_eq!(register("y_odd_inc"), 3);
+        assert_eq!(register("read_mode") & 0x3000, 0x3000);
+
+        write("skip", "3").unwrap();
+        assert_eq!(read("binning"), "1x1");
+        assert_eq!(read("skip"), "3");
+        assert_eq!(register("x_odd_inc"), 5);
+        assert_eq!(register("read_mode") & 0x3000, 0);
+
+        assert!(write("binning", "3x3").is_err());
+        assert_eq!(read("skip"), "3");
+
+        cam.register_set("sensor").unwrap().write_function("y_addr_start", 125).unwrap();
+        assert!(write("binning", "2x2").is_err());
+    }
+}
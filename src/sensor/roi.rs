@@ -0,0 +1,277 @@
+use super::{Bounds, Camera, RegisterSetting, Script};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::serde_util::{bool_false, one};
+
+const PARAMETERS: &[&str] = &[
+    "x_addr_start",
+    "x_addr_end",
+    "y_addr_start",
+    "y_addr_end",
+    "x_odd_inc",
+    "y_odd_inc",
+    "frame_length_lines",
+    "line_length_pck",
+];
+
+const BIN_PARAMETERS: &[&str] = &["read_mode_col_bin", "read_mode_row_bin"];
+
+// the readout window of the sensor, written and read as
+// x,y,width,height[,binning] in pixels of the pixel array, the resulting image
+// is width / binning by height / binning pixels
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct Roi {
+    #[fuseable(ro)]
+    register_set: String,
+    // the context the window is set for, if the register set has contexts
+    #[fuseable(ro)]
+    #[serde(default)]
+    context: Option<String>,
+    // the usable columns and rows of the pixel array
+    #[fuseable(ro)]
+    columns: Bounds,
+    #[fuseable(ro)]
+    rows: Bounds,
+    #[fuseable(ro)]
+    width: Bounds,
+    #[fuseable(ro)]
+    height: Bounds,
+    // x and y have to be a multiple of this, width and height a multiple of this
+    // times the binning
+    #[fuseable(ro)]
+    #[serde(default = "one")]
+    align: u64,
+    // also enable the binning of the skipped pixels, instead of only skipping
+    #[fuseable(ro)]
+    #[serde(default = "bool_false")]
+    bin: bool,
+    // frame_length_lines is increased if it is less than the image height plus
+    // this
+    #[fuseable(ro)]
+    #[serde(default)]
+    min_vertical_blanking: u64,
+    #[fuseable(ro)]
+    #[serde(default)]
+    min_line_length_pck: u64,
+}
+
+impl Roi {
+    fn function(&self, regs: &RegisterSetting, parameter: &str) -> Result<String> {
+        regs.context_function(self.context.as_ref().map(String::as_str), parameter)
+    }
+
+    fn read_parameter(&self, regs: &RegisterSetting, parameter: &str) -> Result<u64> {
+        regs.read_function_u64(&self.function(regs, parameter)?)
+    }
+
+    fn write_parameter(&self, regs: &RegisterSetting, parameter: &str, value: u64) -> Result<()> {
+        regs.write_function(&self.function(regs, parameter)?, value)
+    }
+
+    // x, y, width, height and binning, read from the device instead of the shadow
+    // if fresh is set
+    fn read_window(&self, regs: &RegisterSetting, fresh: bool) -> Result<[u64; 5]> {
+        let read = |parameter: &str| -> Result<u64> {
+            if fresh {
+                regs.refresh_function(&self.function(regs, parameter)?)?;
+            }
+
+            self.read_parameter(regs, parameter)
+        };
+
+        let x = read("x_addr_start")?;
+        let x_end = read("x_addr_end")?;
+        let y = read("y_addr_start")?;
+        let y_end = read("y_addr_end")?;
+        let binning = (read("x_odd_inc")? + 1) / 2;
+
+        let width = (x_end + 1).saturating_sub(x);
+        let height = (y_end + 1).saturating_sub(y);
+
+        Ok([x, y, width, height, binning])
+    }
+
+    fn validate(&self, x: u64, y: u64, width: u64, height: u64, binning: u64) -> Result<()> {
+        if !(1..=3).contains(&binning) {
+            return Err(FuseableError::invalid_value(format!(
+                "binning has to be 1, 2 or 3, not {}",
+                binning
+            )));
+        }
+
+        self.width.check("width", width)?;
+        self.height.check("height", height)?;
+        self.columns.check("first column", x)?;
+        self.columns.check("last column", x + width - 1)?;
+        self.rows.check("first row", y)?;
+        self.rows.check("last row", y + height - 1)?;
+
+        if x % self.align != 0 || y % self.align != 0 {
+            return Err(FuseableError::invalid_value(format!(
+                "x ({}) and y ({}) have to be a multiple of {}",
+                x, y, self.align
+            )));
+        }
+
+        let size_align = self.align * binning;
+
+        if width % size_align != 0 || height % size_align != 0 {
+            return Err(FuseableError::invalid_value(format!(
+                "width ({}) and height ({}) have to be a multiple of {} with a binning of {}",
+                width, height, size_align, binning
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn format_window(window: &[u64]) -> String {
+    window.iter().map(u64::to_string).collect::<Vec<_>>().join(",")
+}
+
+impl Script for Roi {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        Ok(format_window(&self.read_window(&regs, false)?))
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let value = String::from_utf8(value)?;
+        let parts = value
+            .trim()
+            .split(',')
+            .map(|part| part.trim().parse::<u64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| {
+                FuseableError::invalid_value(format!(
+                    "could not parse window {}: {}",
+                    value.trim(),
+                    e
+                ))
+            })?;
+
+        let (x, y, width, height, binning) = match parts[..] {
+            [x, y, width, height] => (x, y, width, height, 1),
+            [x, y, width, height, binning] => (x, y, width, height, binning),
+            _ => {
+                return Err(FuseableError::invalid_value(format!(
+                    "expected x,y,width,height[,binning], got {}",
+                    value.trim()
+                )))
+            }
+        };
+
+        self.validate(x, y, width, height, binning)?;
+
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        let frame_length_lines = self.read_parameter(&regs, "frame_length_lines")?;
+        let min_frame_length_lines = height / binning + self.min_vertical_blanking;
+        let line_length_pck = self.read_parameter(&regs, "line_length_pck")?;
+
+        request.check_deadline()?;
+
+        regs.with_hold(|| {
+            self.write_parameter(&regs, "x_addr_start", x)?;
+            self.write_parameter(&regs, "x_addr_end", x + width - 1)?;
+            self.write_parameter(&regs, "y_addr_start", y)?;
+            self.write_parameter(&regs, "y_addr_end", y + height - 1)?;
+            self.write_parameter(&regs, "x_odd_inc", 2 * binning - 1)?;
+            self.write_parameter(&regs, "y_odd_inc", 2 * binning - 1)?;
+
+            if self.bin {
+                for parameter in BIN_PARAMETERS {
+                    self.write_parameter(&regs, parameter, if binning > 1 { 1 } else { 0 })?;
+                }
+            }
+
+            if frame_length_lines < min_frame_length_lines {
+                self.write_parameter(&regs, "frame_length_lines", min_frame_length_lines)?;
+            }
+
+            if line_length_pck < self.min_line_length_pck {
+                self.write_parameter(&regs, "line_length_pck", self.min_line_length_pck)?;
+            }
+
+            Ok(())
+        })?;
+
+        // the sensor ignores or changes addresses it does not support, so the
+        // window is read back from it
+        let written = [x, y, width, height, binning];
+        let effective = self.read_window(&regs, true)?;
+
+        if effective != written {
+            return Err(format_err!(
+                "the sensor applied the window {} instead of {}",
+                format_window(&effective),
+                format_window(&written)
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+        let context = self.context.as_ref().map(String::as_str);
+
+        regs.check_functions(context, PARAMETERS)?;
+
+        if self.bin {
+            regs.check_functions(context, BIN_PARAMETERS)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensor::{testing::ar0330, Script};
+    use fuseable::Request;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn write_window_test() {
+        let (cam, memories) = ar0330();
+        let roi = cam.scripts["roi"].as_ref();
+        let read = || Script::read(roi, &cam, &Request::default()).unwrap();
+        let write = |window: &str| Script::write(roi, &cam, &Request::default(), window.into());
+
+        write("100,50,1920,1080,2").unwrap();
+        assert_eq!(read(), "100,50,1920,1080,2");
+
+        {
+            let regs = cam.register_set("sensor").unwrap();
+            let value = |name: &str| memories["sensor"].value(&regs.map[name].address);
+
+            assert_eq!(value("x_addr_end"), 2019);
+            assert_eq!(value("y_addr_end"), 1129);
+            assert_eq!(value("x_odd_inc"), 3);
+            assert_eq!(value("y_odd_inc"), 3);
+            assert_eq!(value("grouped_parameter_hold"), 0);
+        }
+
+        write("6,6,640,480").unwrap();
+        assert_eq!(read(), "6,6,640,480,1");
+
+        assert!(write("7,6,640,480").is_err());
+        assert!(write("6,6,640,480,4").is_err());
+        assert_eq!(read(), "6,6,640,480,1");
+    }
+}
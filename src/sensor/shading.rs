@@ -0,0 +1,262 @@
+use super::{Camera, RegisterSetting, Script};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// the color channels as they are named in the coefficient registers
+const CHANNELS: &[&str] = &["rd", "gr", "gb", "bl"];
+const ORDER: usize = 5;
+
+// a coefficient register consists of a sign bit, followed by the exponent and
+// the mantissa, its value is (-1)^sign * mantissa * 2^(exponent - exponent_bias)
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+struct CoefficientEncoding {
+    #[fuseable(ro)]
+    exponent_bits: u32,
+    #[fuseable(ro)]
+    exponent_bias: i32,
+}
+
+impl CoefficientEncoding {
+    fn mantissa_bits(&self) -> u32 { 15 - self.exponent_bits }
+
+    fn encode(&self, value: f64) -> Result<u16> {
+        let sign = if value < 0.0 { 0x8000 } else { 0 };
+        let max_mantissa = f64::from(1u32 << self.mantissa_bits());
+
+        // the smallest exponent where the mantissa still fits gives the best precision
+        for exponent in 0..(1i32 << self.exponent_bits) {
+            let mantissa = (value.abs() / 2f64.powi(exponent - self.exponent_bias)).round();
+
+            if mantissa < max_mantissa {
+                if mantissa == 0.0 {
+                    return Ok(0);
+                }
+
+                return Ok(sign | ((exponent as u16) << self.mantissa_bits()) | mantissa as u16);
+            }
+        }
+
+        Err(FuseableError::invalid_value(format!(
+            "coefficient {} is too large to be encoded",
+            value
+        )))
+    }
+
+    fn decode(&self, raw: u16) -> f64 {
+        let sign = if raw & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = i32::from((raw & 0x7FFF) >> self.mantissa_bits());
+        let mantissa = raw & ((1 << self.mantissa_bits()) - 1);
+
+        sign * f64::from(mantissa) * 2f64.powi(exponent - self.exponent_bias)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Origin {
+    column: u64,
+    row: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ChannelCoefficients {
+    // coefficients[p][q] is the coefficient PpQq
+    coefficients: Vec<Vec<f64>>,
+    q5: f64,
+}
+
+// the calibration file format, this is also what is read back from the sensor
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Calibration {
+    enable: bool,
+    origin: Origin,
+    channels: BTreeMap<String, ChannelCoefficients>,
+}
+
+fn coefficient_register(channel: &str, p: usize, q: usize) -> String {
+    format!("p_{}_p{}q{}", channel, p, q)
+}
+
+fn q5_register(channel: &str) -> String { format!("p_{}_q5", channel) }
+
+// uploads the polynomial lens shading correction from a calibration file
+// written to value, reading value gives the current correction in the same
+// format
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct Shading {
+    #[fuseable(ro)]
+    register_set: String,
+    #[fuseable(ro)]
+    encoding: CoefficientEncoding,
+}
+
+impl Shading {
+    fn validate(&self, calibration: &Calibration) -> Result<()> {
+        for channel in CHANNELS {
+            let coefficients = &calibration
+                .channels
+                .get(*channel)
+                .ok_or_else(|| {
+                    FuseableError::invalid_value(format!(
+                        "the calibration is missing channel {}",
+                        channel
+                    ))
+                })?
+                .coefficients;
+
+            if coefficients.len() != ORDER || coefficients.iter().any(|q| q.len() != ORDER) {
+                return Err(FuseableError::invalid_value(format!(
+                    "expected {}x{} coefficients for channel {}",
+                    ORDER, ORDER, channel
+                )));
+            }
+        }
+
+        let unknown = calibration.channels.keys().find(|c| !CHANNELS.contains(&c.as_str()));
+
+        if let Some(channel) = unknown {
+            return Err(FuseableError::invalid_value(format!(
+                "unknown channel {}, expected one of {:?}",
+                channel, CHANNELS
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn write_coefficient(&self, regs: &RegisterSetting, register: &str, value: f64) -> Result<()> {
+        let encoded = self.encoding.encode(value).map_err(|e| {
+            FuseableError::invalid_value(format!("could not encode {}: {}", register, e))
+        })?;
+
+        regs.write_register(register, encoded)
+    }
+
+    fn read_coefficient(&self, regs: &RegisterSetting, register: &str) -> Result<f64> {
+        Ok(self.encoding.decode(regs.read_register_u64(register)? as u16))
+    }
+}
+
+impl Script for Shading {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        let mut channels = BTreeMap::new();
+
+        for channel in CHANNELS {
+            request.check_deadline()?;
+
+            let coefficients = (0..ORDER)
+                .map(|p| {
+                    (0..ORDER)
+                        .map(|q| self.read_coefficient(&regs, &coefficient_register(channel, p, q)))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let q5 = self.read_coefficient(&regs, &q5_register(channel))?;
+
+            channels.insert(channel.to_string(), ChannelCoefficients { coefficients, q5 });
+        }
+
+        let calibration = Calibration {
+            enable: regs.read_function_u64("poly_sc_enable")? != 0,
+            origin: Origin {
+                column: regs.read_function_u64("poly_origin_c")?,
+                row: regs.read_function_u64("poly_origin_r")?,
+            },
+            channels,
+        };
+
+        serde_yaml::to_string(&calibration).map_err(|e| e.into())
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let calibration: Calibration = serde_yaml::from_slice(&value)?;
+
+        self.validate(&calibration)?;
+
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        // the correction is disabled while the coefficients are inconsistent
+        regs.write_function("poly_sc_enable", 0)?;
+
+        regs.with_hold(|| {
+            regs.write_function("poly_origin_c", calibration.origin.column)?;
+            regs.write_function("poly_origin_r", calibration.origin.row)?;
+
+            for (channel, coefficients) in &calibration.channels {
+                for (p, row) in coefficients.coefficients.iter().enumerate() {
+                    for (q, value) in row.iter().enumerate() {
+                        let register = coefficient_register(channel, p, q);
+                        self.write_coefficient(&regs, &register, *value)?;
+                    }
+                }
+
+                self.write_coefficient(&regs, &q5_register(channel), coefficients.q5)?;
+            }
+
+            Ok(())
+        })?;
+
+        if calibration.enable {
+            regs.write_function("poly_sc_enable", 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> {
+        if self.encoding.exponent_bits == 0 || self.encoding.exponent_bits > 14 {
+            return Err(format_err!("the exponent has to be between 1 and 14 bits wide"));
+        }
+
+        let regs = cam.register_set(&self.register_set)?;
+
+        regs.check_functions(None, &["poly_sc_enable", "poly_origin_c", "poly_origin_r"])?;
+
+        for channel in CHANNELS {
+            let registers = (0..ORDER)
+                .flat_map(|p| (0..ORDER).map(move |q| coefficient_register(channel, p, q)))
+                .chain(std::iter::once(q5_register(channel)));
+
+            for register in registers {
+                if !regs.map.contains_key(&register) {
+                    return Err(format_err!("could not find register {}", register));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoefficientEncoding;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn coefficient_encoding_test() {
+        let encoding = CoefficientEncoding { exponent_bits: 5, exponent_bias: 24 };
+
+        for value in &[0.0, 1.0, -1.0, 0.5, 3.25, -1234.5, 1.0e-4] {
+            let decoded = encoding.decode(encoding.encode(*value).unwrap());
+            assert!((decoded - value).abs() <= value.abs() / 1000.0, "{} != {}", decoded, value);
+        }
+
+        assert_eq!(encoding.encode(1.0).unwrap() >> 15, 0);
+        assert_eq!(encoding.encode(-1.0).unwrap() >> 15, 1);
+        assert!(encoding.encode(1.0e12).is_err());
+    }
+}
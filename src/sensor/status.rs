@@ -0,0 +1,278 @@
+use super::{Camera, RegisterSetting};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+fn default_sample_ms() -> u64 { 500 }
+
+// read only view of the status registers of a register set, the functions that
+// are slices of a status register are shown as its decoded flags
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    register_set: String,
+    registers: Vec<String>,
+    // register counting the output frames, used to measure the frame rate and
+    // to detect stalls
+    #[serde(default)]
+    frame_counter: Option<String>,
+    // how often the poller samples the frame counter
+    #[serde(default = "default_sample_ms")]
+    sample_ms: u64,
+    #[serde(skip)]
+    measurement: Arc<Mutex<Measurement>>,
+}
+
+#[derive(Debug, Default)]
+struct Measurement {
+    last: Option<(Instant, u64)>,
+    fps: Option<f64>,
+}
+
+// samples the frame counter of a status from the poller thread, so reading fps
+// and stalled does not have to wait for a measurement
+pub struct FrameSampler {
+    register_set: Arc<Mutex<RegisterSetting>>,
+    counter: String,
+    interval: Duration,
+    measurement: Arc<Mutex<Measurement>>,
+}
+
+impl FrameSampler {
+    pub fn interval(&self) -> Duration { self.interval }
+
+    // takes a sample if the last one is at least interval old and computes the
+    // frames per second since then
+    pub fn sample(&self) -> Result<()> { self.sample_at(Instant::now()) }
+
+    fn sample_at(&self, now: Instant) -> Result<()> {
+        let mut measurement = self.measurement.lock().unwrap();
+
+        if let Some((start, _)) = measurement.last {
+            if now.duration_since(start) < self.interval {
+                return Ok(());
+            }
+        }
+
+        let count = self.register_set.lock().unwrap().read_register_u64(&self.counter)?;
+
+        if let Some((start, first)) = measurement.last {
+            // the counter is 16 bit and wraps around
+            let frames = count.wrapping_sub(first) & 0xFFFF;
+            let elapsed = now.duration_since(start);
+            let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+            measurement.fps = Some(frames as f64 / seconds);
+        }
+
+        measurement.last = Some((now, count));
+
+        Ok(())
+    }
+}
+
+impl Status {
+    fn flags(&self, regs: &RegisterSetting, register: &str) -> Result<Vec<String>> {
+        let base = &regs
+            .map
+            .get(register)
+            .ok_or_else(|| FuseableError::not_found(register))?
+            .address
+            .base;
+
+        let mut flags: Vec<String> = regs
+            .functions
+            .iter()
+            .filter(|(_, function)| function.addr.base == *base)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        flags.sort();
+
+        Ok(flags)
+    }
+
+    fn register(&self, name: &str) -> Result<&str> {
+        self.registers
+            .iter()
+            .find(|r| *r == name)
+            .map(String::as_str)
+            .ok_or_else(|| FuseableError::not_found(name))
+    }
+
+    // the frame rate measured by the last two samples of the poller
+    fn fps(&self) -> Result<f64> {
+        self.measurement
+            .lock()
+            .unwrap()
+            .fps
+            .ok_or_else(|| format_err!("the frame rate was not measured yet"))
+    }
+
+    // waiting for the lock of the register set can take long, so the deadline is
+    // checked once it is locked
+    fn register_set<'a>(
+        &self,
+        cam: &'a Camera,
+        request: &Request,
+    ) -> Result<MutexGuard<'a, RegisterSetting>> {
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+
+        Ok(regs)
+    }
+
+    pub fn sampler(&self, cam: &Camera) -> Option<FrameSampler> {
+        let counter = self.frame_counter.clone()?;
+
+        Some(FrameSampler {
+            register_set: cam.registers.get(&self.register_set)?.clone(),
+            counter,
+            interval: Duration::from_millis(self.sample_ms),
+            measurement: self.measurement.clone(),
+        })
+    }
+
+    pub fn is_dir(&self, cam: &Camera, path: &mut dyn Iterator<Item = &str>) -> Result<bool> {
+        match (path.next(), path.next()) {
+            (None, _) => Ok(true),
+            (Some("fps"), None) | (Some("stalled"), None) if self.frame_counter.is_some() => {
+                Ok(false)
+            }
+            (Some(name), None) => {
+                let regs = cam.register_set(&self.register_set)?;
+                Ok(!self.flags(&regs, self.register(name)?)?.is_empty())
+            }
+            (Some(name), Some("raw")) => self.register(name).map(|_| false),
+            (Some(name), Some(flag)) => {
+                let regs = cam.register_set(&self.register_set)?;
+
+                if self.flags(&regs, self.register(name)?)?.iter().any(|f| f == flag) {
+                    Ok(false)
+                } else {
+                    Err(FuseableError::not_found(flag))
+                }
+            }
+        }
+    }
+
+    pub fn read(
+        &self,
+        cam: &Camera,
+        request: &Request,
+        path: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Either<Vec<String>, String>> {
+        match (path.next(), path.next()) {
+            (None, _) => {
+                let mut entries = self.registers.clone();
+
+                if self.frame_counter.is_some() {
+                    entries.push("fps".to_owned());
+                    entries.push("stalled".to_owned());
+                }
+
+                Ok(Either::Left(entries))
+            }
+            (Some("fps"), None) if self.frame_counter.is_some() => {
+                self.fps().map(|fps| Either::Right(format!("{:.3}", fps)))
+            }
+            (Some("stalled"), None) if self.frame_counter.is_some() => {
+                self.fps().map(|fps| Either::Right((fps == 0.0).to_string()))
+            }
+            (Some(name), None) => {
+                let regs = self.register_set(cam, request)?;
+                let register = self.register(name)?;
+                let flags = self.flags(&regs, register)?;
+
+                if flags.is_empty() {
+                    regs.read_register(register).map(Either::Right)
+                } else {
+                    let mut entries = vec!["raw".to_owned()];
+                    entries.extend(flags);
+                    Ok(Either::Left(entries))
+                }
+            }
+            (Some(name), Some("raw")) => {
+                let regs = self.register_set(cam, request)?;
+                regs.read_register(self.register(name)?).map(Either::Right)
+            }
+            (Some(name), Some(flag)) => {
+                let regs = self.register_set(cam, request)?;
+
+                if self.flags(&regs, self.register(name)?)?.iter().any(|f| f == flag) {
+                    regs.read_function(flag).map(Either::Right)
+                } else {
+                    Err(FuseableError::not_found(flag))
+                }
+            }
+        }
+    }
+
+    pub fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        for register in self.registers.iter().chain(self.frame_counter.iter()) {
+            if !regs.map.contains_key(register) {
+                return Err(format_err!("could not find status register {}", register));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensor::testing::ar0330;
+    use fuseable::{Either, Request};
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn fps_test() {
+        let (cam, memories) = ar0330();
+        let status = cam.status.as_ref().unwrap();
+        let sampler = status.sampler(&cam).unwrap();
+
+        let read = |entry: &str| match status.read(
+            &cam,
+            &Request::default(),
+            &mut vec![entry].into_iter(),
+        ) {
+            Ok(Either::Right(value)) => Some(value),
+            Ok(entries) => panic!("got entries {:?} for {}", entries, entry),
+            Err(_) => None,
+        };
+        let count = |count: u64| {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].set_value(&regs.map["frame_count"].address, count)
+        };
+        let start = Instant::now();
+
+        count(0xFFF0);
+        sampler.sample_at(start).unwrap();
+        assert_eq!(read("fps"), None);
+
+        // samples closer than the interval are skipped
+        count(0xFFF8);
+        sampler.sample_at(start + Duration::from_millis(100)).unwrap();
+        assert_eq!(read("fps"), None);
+
+        // the counter wraps around after 0xFFFF
+        count(0x000E);
+        sampler.sample_at(start + Duration::from_secs(1)).unwrap();
+        assert_eq!(read("fps"), Some("30.000".to_owned()));
+        assert_eq!(read("stalled"), Some("false".to_owned()));
+
+        count(0x001D);
+        sampler.sample_at(start + Duration::from_millis(1500)).unwrap();
+        assert_eq!(read("fps"), Some("30.000".to_owned()));
+
+        sampler.sample_at(start + Duration::from_millis(2000)).unwrap();
+        assert_eq!(read("fps"), Some("0.000".to_owned()));
+        assert_eq!(read("stalled"), Some("true".to_owned()));
+    }
+}
@@ -0,0 +1,254 @@
+use super::{Camera, RegisterSetting, Script};
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+const MODE: &str = "test_pattern_mode";
+const NORMAL: &str = "disabled";
+const SOLID_COLOR: &str = "solid_color";
+// register that turns off the corrections of the test pattern, so it reaches
+// the output as generated
+const RAW_MODE: &str = "test_raw_mode";
+const RAW: &str = "raw";
+
+// in the order they are written and read: red, green in red rows, green in blue
+// rows, blue
+const COLORS: &[&str] =
+    &["test_data_red", "test_data_greenr", "test_data_greenb", "test_data_blue"];
+
+#[derive(Debug, Serialize, Fuseable)]
+struct SavedState {
+    #[fuseable(ro)]
+    mode: String,
+    #[fuseable(ro)]
+    colors: Vec<u64>,
+    #[fuseable(ro)]
+    raw_mode: u64,
+}
+
+// selects the test pattern by name, the names are the ones of the valuemap of
+// test_pattern_mode, solid_color can be followed by r,g,b or r,gr,gb,b values.
+// a trailing raw turns off the corrections using test_raw_mode. the state
+// before the first pattern was selected is restored by writing restore
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct TestPattern {
+    #[fuseable(ro)]
+    register_set: String,
+    #[fuseable(ro)]
+    #[serde(skip)]
+    saved: Mutex<Option<SavedState>>,
+}
+
+impl TestPattern {
+    fn read_colors(&self, regs: &RegisterSetting) -> Result<Vec<u64>> {
+        COLORS.iter().map(|color| regs.read_function_u64(color)).collect()
+    }
+
+    fn write_colors(&self, regs: &RegisterSetting, colors: &[u64]) -> Result<()> {
+        for (color, value) in COLORS.iter().zip(colors) {
+            let max = regs.max_function_value(color)?;
+
+            if *value > max {
+                return Err(FuseableError::invalid_value(format!(
+                    "{} is larger than the maximum of {}",
+                    value, max
+                )));
+            }
+        }
+
+        COLORS.iter().zip(colors).try_for_each(|(color, value)| regs.write_function(color, value))
+    }
+
+    fn parse_colors(value: &str) -> Result<Vec<u64>> {
+        let colors = value
+            .split(',')
+            .map(|color| color.trim().parse::<u64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| {
+                FuseableError::invalid_value(format!("could not parse color {}: {}", value, e))
+            })?;
+
+        match colors[..] {
+            [red, green, blue] => Ok(vec![red, green, green, blue]),
+            [_, _, _, _] => Ok(colors.clone()),
+            _ => Err(FuseableError::invalid_value(format!(
+                "expected r,g,b or r,gr,gb,b, got {}",
+                value
+            ))),
+        }
+    }
+
+    fn restore(&self, regs: &RegisterSetting) -> Result<()> {
+        let saved = self.saved.lock().unwrap().take();
+
+        regs.with_hold(|| match saved {
+            Some(SavedState { mode, colors, raw_mode }) => {
+                self.write_colors(regs, &colors)?;
+                regs.write_register(RAW_MODE, raw_mode)?;
+                regs.write_function(MODE, mode)
+            }
+            None => {
+                regs.write_register(RAW_MODE, 0)?;
+                regs.write_function(MODE, NORMAL)
+            }
+        })
+    }
+}
+
+impl Script for TestPattern {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+        let mode = regs.read_function(MODE)?;
+
+        let mut pattern = if mode.trim() == SOLID_COLOR {
+            let colors = self.read_colors(&regs)?;
+            let colors: Vec<_> = colors.iter().map(u64::to_string).collect();
+
+            format!("{} {}", SOLID_COLOR, colors.join(","))
+        } else {
+            mode.trim().to_owned()
+        };
+
+        if regs.read_register_u64(RAW_MODE)? != 0 {
+            pattern.push(' ');
+            pattern.push_str(RAW);
+        }
+
+        Ok(pattern)
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+        request.check_deadline()?;
+        let value = String::from_utf8(value)?;
+        let value = value.trim();
+        let (value, raw) = match value.rsplitn(2, ' ').collect::<Vec<_>>()[..] {
+            [RAW, rest] => (rest.trim(), true),
+            _ => (value, false),
+        };
+        let mut parts = value.splitn(2, ' ');
+        let pattern = parts.next().unwrap_or("");
+        let colors = parts.next().map(str::trim);
+
+        if pattern == "restore" {
+            return self.restore(&regs);
+        }
+
+        let colors = match (pattern, colors) {
+            (SOLID_COLOR, Some(colors)) => Some(Self::parse_colors(colors)?),
+            (_, Some(_)) => {
+                return Err(FuseableError::invalid_value(format!(
+                    "only {} takes colors",
+                    SOLID_COLOR
+                )))
+            }
+            (_, None) => None,
+        };
+
+        {
+            let mut saved = self.saved.lock().unwrap();
+
+            if saved.is_none() {
+                let mode = regs.read_function(MODE)?.trim().to_owned();
+
+                if mode == NORMAL {
+                    let colors = self.read_colors(&regs)?;
+                    let raw_mode = regs.read_register_u64(RAW_MODE)?;
+
+                    *saved = Some(SavedState { mode, colors, raw_mode });
+                }
+            }
+        }
+
+        request.check_deadline()?;
+
+        regs.with_hold(|| {
+            if let Some(ref colors) = colors {
+                self.write_colors(&regs, colors)?;
+            }
+
+            regs.write_register(RAW_MODE, if raw { 1 } else { 0 })?;
+            regs.write_function(MODE, pattern)
+        })
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        regs.check_functions(None, &[MODE])?;
+        regs.check_functions(None, COLORS)?;
+
+        if regs.map.contains_key(RAW_MODE) {
+            Ok(())
+        } else {
+            Err(format_err!("could not find register {}", RAW_MODE))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{COLORS, RAW_MODE};
+    use crate::sensor::{testing::ar0330, Script};
+    use fuseable::Request;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn restore_test() {
+        let (cam, memories) = ar0330();
+        let test_pattern = cam.scripts["test_pattern"].as_ref();
+        let read = || Script::read(test_pattern, &cam, &Request::default()).unwrap();
+        let write =
+            |value: &str| Script::write(test_pattern, &cam, &Request::default(), value.into());
+        let register = |name: &str| {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].value(&regs.map[name].address)
+        };
+        let set = |name: &str, value: u64| {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].set_value(&regs.map[name].address, value)
+        };
+
+        for (color, value) in COLORS.iter().zip(&[100, 200, 300, 400]) {
+            set(color, *value);
+        }
+        set(RAW_MODE, 2);
+        assert_eq!(read(), "disabled raw");
+
+        write("solid_color 4095,0,0 raw").unwrap();
+        assert_eq!(read(), "solid_color 4095,0,0,0 raw");
+        assert_eq!(register(RAW_MODE), 1);
+
+        // the state from before the first pattern is kept
+        write("color_bar").unwrap();
+        assert_eq!(read(), "color_bar");
+
+        assert!(write("color_bar 1,2,3").is_err());
+        assert!(write("solid_color 4096,0,0").is_err());
+        assert_eq!(read(), "color_bar");
+
+        write("restore").unwrap();
+        assert_eq!(read(), "disabled raw");
+        assert_eq!(register(RAW_MODE), 2);
+        let colors: Vec<_> = COLORS.iter().map(|color| register(color)).collect();
+        assert_eq!(colors, vec![100, 200, 300, 400]);
+
+        // without a saved state restore disables the pattern
+        write("walking_ones").unwrap();
+        assert_eq!(read(), "walking_ones");
+        assert_eq!(register("test_pattern_mode"), 0x100);
+        write("restore").unwrap();
+        write("restore").unwrap();
+        assert_eq!(read(), "disabled");
+    }
+}
@@ -0,0 +1,127 @@
+// the shipped ar0330 config with its register sets backed by memory instead of
+// the devices, for testing the scripts against the real register maps
+use super::Camera;
+use crate::{
+    address::Address,
+    communication_channel::{ChannelPolicy, CommChannel},
+    serde_util::FILE_OPENER,
+};
+use failure::format_err;
+use fuseable::{Either, Result};
+use fuseable_derive::Fuseable;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Default)]
+struct Contents {
+    values: HashMap<Vec<u8>, Vec<u8>>,
+    // the base address of every write, in the order they reached the channel
+    writes: Vec<Vec<u8>>,
+    // writes to these base addresses fail
+    failing: Vec<Vec<u8>>,
+}
+
+// a channel that keeps what is written to it, the handles share the contents,
+// so the tests can look at what the camera wrote
+#[derive(Debug, Default, Fuseable)]
+pub struct Memory {
+    #[fuseable(skip)]
+    contents: Arc<Mutex<Contents>>,
+    #[fuseable(skip)]
+    policy: ChannelPolicy,
+}
+
+impl Memory {
+    pub fn handle(&self) -> Memory {
+        Memory { contents: self.contents.clone(), policy: ChannelPolicy::default() }
+    }
+
+    pub fn value(&self, address: &Address) -> u64 {
+        let contents = self.contents.lock().unwrap();
+        let value = contents.values.get(&address.base).cloned().unwrap_or_default();
+
+        value.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b))
+    }
+
+    pub fn set_value(&self, address: &Address, value: u64) {
+        let bytes = address.bytes().unwrap_or(8);
+        let value = value.to_be_bytes()[8 - bytes..].to_vec();
+
+        self.contents.lock().unwrap().values.insert(address.base.clone(), value);
+    }
+
+    pub fn set_failing(&self, address: &Address, failing: bool) {
+        let mut contents = self.contents.lock().unwrap();
+        contents.failing.retain(|base| *base != address.base);
+
+        if failing {
+            contents.failing.push(address.base.clone());
+        }
+    }
+
+    pub fn writes(&self) -> Vec<Vec<u8>> { self.contents.lock().unwrap().writes.clone() }
+}
+
+impl CommChannel for Memory {
+    fn read_value_real(&self, address: &Address) -> Result<Vec<u8>> {
+        let contents = self.contents.lock().unwrap();
+
+        Ok(contents
+            .values
+            .get(&address.base)
+            .cloned()
+            .unwrap_or_else(|| vec![0; address.bytes().unwrap_or(0)]))
+    }
+
+    fn write_value_real(&self, address: &Address, value: Vec<u8>) -> Result<()> {
+        let mut contents = self.contents.lock().unwrap();
+
+        if contents.failing.contains(&address.base) {
+            return Err(format_err!("could not write {}", address));
+        }
+
+        contents.writes.push(address.base.clone());
+        contents.values.insert(address.base.clone(), value);
+
+        Ok(())
+    }
+
+    fn mock_mode(&mut self, _mock: bool) {}
+
+    fn get_mock_mode(&self) -> bool { false }
+
+    fn policy(&self) -> &ChannelPolicy { &self.policy }
+}
+
+// every register starts out with its default value. the write checksum is not
+// emulated, so it is disabled
+pub fn ar0330() -> (Camera, HashMap<String, Memory>) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sensors/ar0330/ar0330.yml");
+    FILE_OPENER.set_path(path.clone());
+
+    let contents = std::fs::read_to_string(path).unwrap();
+    let camera: Camera = serde_yaml::from_str(&contents).unwrap();
+    let mut memories = HashMap::new();
+
+    for (name, regs) in &camera.registers {
+        let mut regs = regs.lock().unwrap();
+        let memory = Memory::default();
+
+        for register in regs.map.values() {
+            if let Some(default) = register.default {
+                memory.set_value(&register.address, default);
+            }
+        }
+
+        regs.channel = Box::new(memory.handle());
+        regs.checksum = None;
+        regs.shadow.clear();
+
+        memories.insert(name.clone(), memory);
+    }
+
+    (camera, memories)
+}
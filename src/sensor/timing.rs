@@ -0,0 +1,368 @@
+use super::{Camera, RegisterSetting, Script};
+use ::log::warn;
+use failure::format_err;
+use fuseable::{Either, FuseableError, Request, Result};
+use fuseable_derive::Fuseable;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::serde_util::one;
+
+const PARAMETERS: &[&str] = &[
+    "pll_multiplier",
+    "pre_pll_clk_div",
+    "vt_sys_clk_div",
+    "vt_pix_clk_div",
+    "line_length_pck",
+    "frame_length_lines",
+    "coarse_integration_time",
+    "y_addr_start",
+    "y_addr_end",
+    "y_odd_inc",
+];
+
+// the clock configuration of the sensor, used to convert between line counts
+// and physical units
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+pub struct Timing {
+    #[fuseable(ro)]
+    register_set: String,
+    #[fuseable(ro)]
+    #[serde(default)]
+    context: Option<String>,
+    // frequency of the external clock of the sensor
+    #[fuseable(ro)]
+    ext_clk_hz: u64,
+    // frame_length_lines has to be at least the image height plus this
+    #[fuseable(ro)]
+    #[serde(default)]
+    min_vertical_blanking: u64,
+    // coarse_integration_time can be at most frame_length_lines minus this
+    #[fuseable(ro)]
+    #[serde(default = "one")]
+    integration_margin: u64,
+    // fine_integration_time is only used if this is set, as the usable range
+    // depends on line_length_pck
+    #[fuseable(ro)]
+    #[serde(default)]
+    max_fine_integration_time: Option<u64>,
+}
+
+impl Timing {
+    fn read_parameter(&self, regs: &RegisterSetting, parameter: &str) -> Result<u64> {
+        regs.read_function_u64(&regs.context_function(self.context(), parameter)?)
+    }
+
+    fn write_parameter(&self, regs: &RegisterSetting, parameter: &str, value: u64) -> Result<()> {
+        regs.write_function(&regs.context_function(self.context(), parameter)?, value)
+    }
+
+    fn context(&self) -> Option<&str> { self.context.as_ref().map(String::as_str) }
+
+    // vt_pix_clk in Hz
+    fn pixel_clock(&self, regs: &RegisterSetting) -> Result<f64> {
+        let multiplier = self.read_parameter(regs, "pll_multiplier")?;
+        let divider = self.read_parameter(regs, "pre_pll_clk_div")?
+            * self.read_parameter(regs, "vt_sys_clk_div")?
+            * self.read_parameter(regs, "vt_pix_clk_div")?;
+
+        if divider == 0 {
+            return Err(format_err!("the pll is not configured, one of its dividers is zero"));
+        }
+
+        Ok(self.ext_clk_hz as f64 * multiplier as f64 / divider as f64)
+    }
+
+    fn min_frame_length_lines(&self, regs: &RegisterSetting) -> Result<u64> {
+        let rows = (self.read_parameter(regs, "y_addr_end")? + 1)
+            .saturating_sub(self.read_parameter(regs, "y_addr_start")?);
+        let binning = (self.read_parameter(regs, "y_odd_inc")? + 1) / 2;
+
+        Ok(rows / binning.max(1) + self.min_vertical_blanking)
+    }
+
+    fn max_coarse_integration_time(&self, frame_length_lines: u64) -> u64 {
+        frame_length_lines.saturating_sub(self.integration_margin)
+    }
+
+    fn frame_time_us(&self, regs: &RegisterSetting) -> Result<f64> {
+        let clocks = self.read_parameter(regs, "line_length_pck")?
+            * self.read_parameter(regs, "frame_length_lines")?;
+
+        Ok(clocks as f64 * 1e6 / self.pixel_clock(regs)?)
+    }
+
+    fn exposure_us(&self, regs: &RegisterSetting) -> Result<f64> {
+        let fine = match self.max_fine_integration_time {
+            Some(_) => self.read_parameter(regs, "fine_integration_time")?,
+            None => 0,
+        };
+
+        let clocks = self.read_parameter(regs, "coarse_integration_time")?
+            * self.read_parameter(regs, "line_length_pck")?
+            + fine;
+
+        Ok(clocks as f64 * 1e6 / self.pixel_clock(regs)?)
+    }
+
+    // sets the exposure, clamped to the frame time
+    fn write_exposure_us(&self, regs: &RegisterSetting, exposure_us: f64) -> Result<()> {
+        let line_length_pck = self.read_parameter(regs, "line_length_pck")?;
+        let frame_length_lines = self.read_parameter(regs, "frame_length_lines")?;
+        let clocks = exposure_us * self.pixel_clock(regs)? / 1e6;
+
+        let (coarse, fine) = match self.max_fine_integration_time {
+            Some(max_fine) => {
+                let coarse = (clocks / line_length_pck as f64).floor() as u64;
+                let fine = (clocks - (coarse * line_length_pck) as f64).round() as u64;
+
+                (coarse, fine.min(max_fine))
+            }
+            None => ((clocks / line_length_pck as f64).round() as u64, 0),
+        };
+
+        let max_coarse = self.max_coarse_integration_time(frame_length_lines);
+
+        let (coarse, fine) = if coarse > max_coarse {
+            warn!(
+                "exposure of {}us is longer than the frame time, clamping to {} lines",
+                exposure_us, max_coarse
+            );
+
+            (max_coarse, 0)
+        } else {
+            (coarse, fine)
+        };
+
+        regs.with_hold(|| {
+            self.write_parameter(regs, "coarse_integration_time", coarse)?;
+
+            if self.max_fine_integration_time.is_some() {
+                self.write_parameter(regs, "fine_integration_time", fine)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> {
+        let regs = cam.register_set(&self.register_set)?;
+
+        regs.check_functions(self.context(), PARAMETERS)?;
+
+        if self.max_fine_integration_time.is_some() {
+            regs.check_functions(self.context(), &["fine_integration_time"])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_f64(value: &[u8]) -> Result<f64> {
+    let value = String::from_utf8(value.to_vec())?;
+    let parsed: f64 = value.trim().parse().map_err(|e| {
+        FuseableError::invalid_value(format!("could not parse {}: {}", value.trim(), e))
+    })?;
+
+    if parsed.is_finite() && parsed > 0.0 {
+        Ok(parsed)
+    } else {
+        Err(FuseableError::invalid_value(format!(
+            "expected a positive number, got {}",
+            value.trim()
+        )))
+    }
+}
+
+// frames per second, written by changing frame_length_lines, the exposure is
+// shortened if it no longer fits into the frame
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct FrameRate {
+    #[fuseable(ro)]
+    #[serde(flatten)]
+    timing: Timing,
+}
+
+impl Script for FrameRate {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.timing.register_set)?;
+        request.check_deadline()?;
+
+        Ok(format!("{:.3}", 1e6 / self.timing.frame_time_us(&regs)?))
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let fps = parse_f64(&value)?;
+        let regs = cam.register_set(&self.timing.register_set)?;
+        request.check_deadline()?;
+        let timing = &self.timing;
+
+        let line_length_pck = timing.read_parameter(&regs, "line_length_pck")?;
+        let pixel_clock = timing.pixel_clock(&regs)?;
+        let min_frame_length_lines = timing.min_frame_length_lines(&regs)?;
+
+        let frame_length_lines = (pixel_clock / (fps * line_length_pck as f64)).round() as u64;
+
+        if frame_length_lines < min_frame_length_lines {
+            return Err(FuseableError::invalid_value(format!(
+                "{} fps is too fast, at most {:.3} fps are possible with the current window",
+                fps,
+                pixel_clock / (min_frame_length_lines * line_length_pck) as f64
+            )));
+        }
+
+        if frame_length_lines > 0xFFFF {
+            return Err(FuseableError::invalid_value(format!(
+                "{} fps is too slow, at least {:.3} fps are needed with the current line length",
+                fps,
+                pixel_clock / (0xFFFF * line_length_pck) as f64
+            )));
+        }
+
+        let coarse = timing.read_parameter(&regs, "coarse_integration_time")?;
+        let max_coarse = timing.max_coarse_integration_time(frame_length_lines);
+
+        request.check_deadline()?;
+
+        regs.with_hold(|| {
+            timing.write_parameter(&regs, "frame_length_lines", frame_length_lines)?;
+
+            if coarse > max_coarse {
+                warn!("exposure no longer fits into the frame, clamping to {} lines", max_coarse);
+
+                timing.write_parameter(&regs, "coarse_integration_time", max_coarse)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> { self.timing.check(cam) }
+}
+
+// exposure time in microseconds, can also be written as a shutter angle
+// relative to the current frame time (for example 180deg)
+#[derive(Debug, Serialize, Deserialize, Fuseable)]
+#[fuseable(virtual_field(
+    name = "value",
+    read = "self.read_value",
+    write = "self.write_value",
+    is_dir = "false",
+    context = "Camera"
+))]
+pub struct Exposure {
+    #[fuseable(ro)]
+    #[serde(flatten)]
+    timing: Timing,
+}
+
+impl Script for Exposure {
+    fn read(&self, cam: &Camera, request: &Request) -> Result<String> {
+        let regs = cam.register_set(&self.timing.register_set)?;
+        request.check_deadline()?;
+
+        Ok(format!("{:.2}", self.timing.exposure_us(&regs)?))
+    }
+
+    fn write(&self, cam: &Camera, request: &Request, value: Vec<u8>) -> Result<()> {
+        let regs = cam.register_set(&self.timing.register_set)?;
+        request.check_deadline()?;
+        let value = String::from_utf8(value)?;
+        let value = value.trim();
+
+        let exposure_us = if value.ends_with("deg") {
+            let angle = parse_f64(value.trim_end_matches("deg").as_bytes())?;
+
+            angle / 360.0 * self.timing.frame_time_us(&regs)?
+        } else {
+            parse_f64(value.as_bytes())?
+        };
+
+        request.check_deadline()?;
+        self.timing.write_exposure_us(&regs, exposure_us)
+    }
+
+    fn check(&self, cam: &Camera) -> Result<()> { self.timing.check(cam) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensor::{testing::ar0330, Script};
+    use fuseable::Request;
+    use pretty_assertions::assert_eq;
+
+    // the register defaults give a pixel clock of 98MHz, a line time of 1248
+    // clocks and 1308 lines per frame
+    #[test]
+    fn exposure_test() {
+        let (cam, memories) = ar0330();
+        let exposure = cam.scripts["exposure"].as_ref();
+        let read = || Script::read(exposure, &cam, &Request::default()).unwrap();
+        let write = |value: &str| Script::write(exposure, &cam, &Request::default(), value.into());
+        let coarse = || {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].value(&regs.map["coarse_integration_time"].address)
+        };
+
+        write("1000").unwrap();
+        assert_eq!(coarse(), 79);
+        assert_eq!(read(), "1006.04");
+
+        write(&read()).unwrap();
+        assert_eq!(coarse(), 79);
+        assert_eq!(read(), "1006.04");
+
+        write("180deg").unwrap();
+        assert_eq!(coarse(), 654);
+
+        write("100000").unwrap();
+        assert_eq!(coarse(), 1307);
+
+        assert!(write("-1").is_err());
+        assert!(write("fast").is_err());
+        assert_eq!(coarse(), 1307);
+    }
+
+    #[test]
+    fn frame_rate_test() {
+        let (cam, memories) = ar0330();
+        let frame_rate = cam.scripts["frame_rate"].as_ref();
+        let read = || Script::read(frame_rate, &cam, &Request::default()).unwrap();
+        let write =
+            |value: &str| Script::write(frame_rate, &cam, &Request::default(), value.into());
+        let register = |name: &str| {
+            let regs = cam.register_set("sensor").unwrap();
+            memories["sensor"].value(&regs.map[name].address)
+        };
+        let set = |name: &str, value: u64| {
+            cam.register_set("sensor").unwrap().write_function(name, value).unwrap()
+        };
+
+        write("30").unwrap();
+        assert_eq!(register("frame_length_lines"), 2618);
+        assert_eq!(read(), "29.995");
+
+        // the exposure is clamped once it no longer fits into the frame
+        set("coarse_integration_time", 2000);
+        write("50").unwrap();
+        assert_eq!(register("frame_length_lines"), 1571);
+        assert_eq!(register("coarse_integration_time"), 1570);
+
+        // frame_length_lines is 16 bits wide, slower frame rates are rejected
+        // instead of being truncated
+        write("1.2").unwrap();
+        assert_eq!(register("frame_length_lines"), 65438);
+        assert!(write("1.19").is_err());
+        assert_eq!(register("frame_length_lines"), 65438);
+
+        // the window needs 1296 rows plus 16 rows of vertical blanking
+        assert!(write("60").is_err());
+        assert_eq!(register("frame_length_lines"), 65438);
+    }
+}
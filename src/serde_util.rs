@@ -1,6 +1,12 @@
 use lazy_static::lazy_static;
-use serde::{Deserialize, Deserializer};
-use std::{fs::File, io::Read, path::PathBuf, sync::Mutex};
+use serde::{de::Error, Deserialize, Deserializer};
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io::Read,
+    path::PathBuf,
+    sync::Mutex,
+};
 
 pub struct FileOpener {
     path: Mutex<Option<PathBuf>>,
@@ -42,3 +48,71 @@ pub fn bool_false() -> bool { false }
 
 #[allow(dead_code)]
 pub fn bool_true() -> bool { true }
+
+pub fn one() -> u64 { 1 }
+
+// users and groups can be given by name or by id
+fn lookup_id<'de, D>(
+    deserializer: D,
+    lookup: impl FnOnce(&CStr) -> Option<u32>,
+) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = match Option::<String>::deserialize(deserializer)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    if let Ok(id) = name.parse() {
+        return Ok(Some(id));
+    }
+
+    let c_name = CString::new(name.clone()).map_err(D::Error::custom)?;
+
+    lookup(&c_name)
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("unknown user or group {}", name)))
+}
+
+pub fn user_id<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    lookup_id(deserializer, |name| {
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut result = std::ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+
+        if ret == 0 && !result.is_null() {
+            Some(passwd.pw_uid)
+        } else {
+            None
+        }
+    })
+}
+
+pub fn group_id<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    lookup_id(deserializer, |name| {
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut result = std::ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+
+        if ret == 0 && !result.is_null() {
+            Some(group.gr_gid)
+        } else {
+            None
+        }
+    })
+}
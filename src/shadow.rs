@@ -0,0 +1,24 @@
+use std::{collections::HashMap, sync::Mutex};
+
+// last known contents of the registers of a register set, keyed by the base
+// address of the register
+#[derive(Debug, Default)]
+pub struct Shadow {
+    values: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Shadow {
+    pub fn get(&self, base: &[u8]) -> Option<Vec<u8>> {
+        self.values.lock().unwrap().get(base).cloned()
+    }
+
+    pub fn set(&self, base: Vec<u8>, value: Vec<u8>) {
+        self.values.lock().unwrap().insert(base, value);
+    }
+
+    pub fn invalidate(&self, base: &[u8]) { self.values.lock().unwrap().remove(base); }
+
+    pub fn clear(&self) { self.values.lock().unwrap().clear(); }
+
+    pub fn cached(&self) -> Vec<Vec<u8>> { self.values.lock().unwrap().keys().cloned().collect() }
+}
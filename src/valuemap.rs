@@ -1,7 +1,7 @@
 use ::log::{log, trace};
 use byteorder::{BigEndian, ReadBytesExt};
 use failure::format_err;
-use fuseable::{Either, Result};
+use fuseable::{Either, FuseableError, Result};
 use fuseable_derive::Fuseable;
 use isomorphism::BiMap;
 use parse_num::{parse_num, parse_num_padded, ParseError};
@@ -51,23 +51,45 @@ pub enum ValueMap {
 }
 
 impl ValueMap {
+    // the keys keep the leading zeros they were written with, while values read
+    // from a register are padded to its width, so both are compared without them
+    fn key(&self, v: &[u8]) -> Value {
+        fn trim(v: &[u8]) -> &[u8] { &v[v.iter().take_while(|b| **b == 0).count()..] }
+
+        let keys: Vec<&Value> = match self {
+            ValueMap::Keywords(map) => map.iter().map(|(k, _)| k).collect(),
+            ValueMap::Floating(map) => map.keys().collect(),
+            ValueMap::Fixed(map) => map.keys().collect(),
+        };
+
+        keys.into_iter()
+            .find(|k| match k {
+                Value::Value(k) => trim(k) == trim(v),
+                Value::Any => false,
+            })
+            .cloned()
+            .unwrap_or_else(|| Value::Value(v.to_vec()))
+    }
+
     pub fn lookup(&self, v: Vec<u8>) -> Result<String> {
+        let key = self.key(&v);
+
         match self {
-            ValueMap::Keywords(map) => match map.get_left(&Value::Value(v.clone())) {
+            ValueMap::Keywords(map) => match map.get_left(&key) {
                 Some(v) => Ok(v.clone()),
                 None => match map.get_left(&Value::Any) {
                     Some(v) => Ok(v.clone()),
                     None => Err(format_err!("could not find {:?} in valuemap {:?}", v, self)),
                 },
             },
-            ValueMap::Floating(map) => match map.get(&Value::Value(v.clone())) {
+            ValueMap::Floating(map) => match map.get(&key) {
                 Some(v) => Ok(v.to_string()),
                 None => match map.get(&Value::Any) {
                     Some(v) => Ok(v.to_string()),
                     None => Err(format_err!("could not find {:?} in valuemap {:?}", v, self)),
                 },
             },
-            ValueMap::Fixed(map) => match map.get(&Value::Value(v.clone())) {
+            ValueMap::Fixed(map) => match map.get(&key) {
                 Some(v) => Ok(v.to_string()),
                 None => match map.get(&Value::Any) {
                     Some(v) => Ok(v.to_string()),
@@ -80,9 +102,12 @@ impl ValueMap {
     pub fn encode(&self, s: String) -> Result<Vec<u8>> {
         match self {
             ValueMap::Keywords(map) => {
-                let v = map
-                    .get_right(&s)
-                    .ok_or_else(|| format_err!("could not find {:?} in valuemap {:?}", s, self))?;
+                let v = map.get_right(&s).ok_or_else(|| {
+                    FuseableError::invalid_value(format!(
+                        "could not find {:?} in valuemap {:?}",
+                        s, self
+                    ))
+                })?;
                 match v {
                     Value::Value(v) => Ok(v.clone()),
                     _ => {
@@ -121,7 +146,12 @@ impl ValueMap {
                             Ordering::Greater
                         }
                     })
-                    .ok_or_else(|| format_err!("could not find {:?} in valuemap {:?}", s, self))?;
+                    .ok_or_else(|| {
+                        FuseableError::invalid_value(format!(
+                            "could not find {:?} in valuemap {:?}",
+                            s, self
+                        ))
+                    })?;
 
                 match v {
                     Value::Value(v) => Ok(v.clone()),
@@ -150,13 +180,24 @@ impl ValueMap {
                 }
             }
             ValueMap::Fixed(map) => {
-                let wanted_value: u64 =
-                    Cursor::new(parse_num(s.clone())?).read_u64::<BigEndian>()?;
+                // parse_num only returns as many bytes as the number needs
+                let bytes = parse_num(s.clone())?;
 
-                let (v, _) = map
-                    .iter()
-                    .find(|(_, v)| **v == wanted_value)
-                    .ok_or_else(|| format_err!("could not find {} in valuemap {:?}", s, self))?;
+                if bytes.is_empty() || bytes.len() > 8 {
+                    return Err(FuseableError::invalid_value(format!(
+                        "{} does not fit into 64 bits",
+                        s
+                    )));
+                }
+
+                let wanted_value: u64 = Cursor::new(&bytes).read_uint::<BigEndian>(bytes.len())?;
+
+                let (v, _) = map.iter().find(|(_, v)| **v == wanted_value).ok_or_else(|| {
+                    FuseableError::invalid_value(format!(
+                        "could not find {} in valuemap {:?}",
+                        s, self
+                    ))
+                })?;
 
                 match v {
                     Value::Value(v) => Ok(v.clone()),
@@ -0,0 +1,201 @@
+use fuseable::Result;
+use serde_derive::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+fn hundred_ms() -> u64 { 100 }
+
+// called with the path and the new raw value when a watched value changes,
+// this happens while the register set is locked, so the callback must not
+// access the register set itself
+pub type Callback = Box<dyn Fn(&str, &[u8]) + Send>;
+
+#[derive(Default)]
+struct Watched {
+    value: Option<Vec<u8>>,
+    // the first poll counts as a change, so this is the time watching started
+    // until the value changes for the first time
+    modified: Option<SystemTime>,
+    polled: Option<Instant>,
+    callbacks: Vec<Callback>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    // keyed by the path inside the register set, for example map/frame_count
+    watched: BTreeMap<String, Watched>,
+    // raw values read during the current interval keyed by the address that was
+    // read, so watches on the same register share one bus access
+    reads: HashMap<String, (Instant, Vec<u8>)>,
+}
+
+impl fmt::Debug for WatchState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.watched.keys()).finish()
+    }
+}
+
+// registers and functions of a register set that are polled for changes, the
+// time of the last change is reported as the mtime of their value file
+//
+// polling happens when poll_watches is called (the ctrl daemon runs a Poller
+// thread for this), but at most once every interval. fuse_mt 0.5 has no way to
+// send notify_inval_inode or poll events, so the kernel (and thus inotify) is
+// not told about changes, tools have to stat the value files
+#[derive(Debug, Deserialize)]
+pub struct Watches {
+    #[serde(default = "hundred_ms")]
+    interval_ms: u64,
+    // watched from the start
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(skip)]
+    state: Mutex<WatchState>,
+}
+
+impl Default for Watches {
+    fn default() -> Watches {
+        Watches { interval_ms: hundred_ms(), paths: Vec::new(), state: Mutex::default() }
+    }
+}
+
+impl Watches {
+    fn due(&self, last: Option<Instant>, now: Instant) -> bool {
+        last.map(|last| now.duration_since(last) >= Duration::from_millis(self.interval_ms))
+            .unwrap_or(true)
+    }
+
+    pub fn interval(&self) -> Duration { Duration::from_millis(self.interval_ms) }
+
+    pub fn add(&self, path: &str) {
+        self.state.lock().unwrap().watched.entry(path.to_owned()).or_default();
+    }
+
+    pub fn subscribe(&self, path: &str, callback: Callback) {
+        let mut state = self.state.lock().unwrap();
+        state.watched.entry(path.to_owned()).or_default().callbacks.push(callback);
+    }
+
+    pub fn remove(&self, path: &str) -> bool {
+        self.state.lock().unwrap().watched.remove(path).is_some()
+    }
+
+    pub fn watched(&self) -> Vec<String> {
+        self.state.lock().unwrap().watched.keys().cloned().collect()
+    }
+
+    pub fn modified(&self, path: &str) -> Option<SystemTime> {
+        self.state.lock().unwrap().watched.get(path).and_then(|w| w.modified)
+    }
+
+    // reads that happened before a write no longer reflect the device
+    pub fn forget_reads(&self) { self.state.lock().unwrap().reads.clear(); }
+
+    // reads using read, unless key was already read during this interval
+    pub fn read(&self, key: String, read: impl FnOnce() -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let now = Instant::now();
+
+        if let Some((last, value)) = self.state.lock().unwrap().reads.get(&key) {
+            if !self.due(Some(*last), now) {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = read()?;
+        self.state.lock().unwrap().reads.insert(key, (now, value.clone()));
+
+        Ok(value)
+    }
+
+    // polls path if it is due, returns whether its value changed
+    pub fn poll(&self, path: &str, read: impl FnOnce() -> Result<Vec<u8>>) -> Result<bool> {
+        let now = Instant::now();
+
+        match self.state.lock().unwrap().watched.get(path) {
+            Some(watched) if self.due(watched.polled, now) => {}
+            _ => return Ok(false),
+        }
+
+        // the lock is not held while reading, as read goes through self.read
+        let value = read()?;
+
+        let mut state = self.state.lock().unwrap();
+        let watched = match state.watched.get_mut(path) {
+            Some(watched) => watched,
+            None => return Ok(false),
+        };
+
+        watched.polled = Some(now);
+
+        if watched.value.as_ref() == Some(&value) {
+            return Ok(false);
+        }
+
+        if watched.value.is_some() {
+            for callback in &watched.callbacks {
+                callback(path, &value);
+            }
+        }
+
+        watched.value = Some(value);
+        watched.modified = Some(SystemTime::now());
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Watches;
+    use pretty_assertions::assert_eq;
+    use std::{
+        cell::Cell,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn watch_test() {
+        let watches = Watches { interval_ms: 0, ..Watches::default() };
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let value = Cell::new(1u8);
+
+        let recorded = changes.clone();
+        let callback = move |path: &str, value: &[u8]| {
+            recorded.lock().unwrap().push((path.to_owned(), value.to_vec()))
+        };
+        watches.subscribe("map/frame_count", Box::new(callback));
+
+        assert_eq!(watches.poll("map/frame_count", || Ok(vec![value.get()])).unwrap(), true);
+        assert!(watches.modified("map/frame_count").is_some());
+        assert_eq!(watches.poll("map/frame_count", || Ok(vec![value.get()])).unwrap(), false);
+
+        value.set(2);
+        assert_eq!(watches.poll("map/frame_count", || Ok(vec![value.get()])).unwrap(), true);
+        assert_eq!(watches.poll("map/other", || Ok(vec![value.get()])).unwrap(), false);
+
+        // only changes after the first poll are reported
+        assert_eq!(*changes.lock().unwrap(), vec![("map/frame_count".to_owned(), vec![2])]);
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let watches = Watches::default();
+        let reads = Cell::new(0);
+
+        let read = || {
+            reads.set(reads.get() + 1);
+            Ok(vec![reads.get()])
+        };
+
+        assert_eq!(watches.read("0x3000".to_owned(), read).unwrap(), vec![1]);
+        assert_eq!(watches.read("0x3000".to_owned(), read).unwrap(), vec![1]);
+        assert_eq!(watches.read("0x3002".to_owned(), read).unwrap(), vec![2]);
+
+        watches.forget_reads();
+        assert_eq!(watches.read("0x3000".to_owned(), read).unwrap(), vec![3]);
+    }
+}